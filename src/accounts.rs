@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::assets::sha256;
+
+// A player. Scores belong to the id, so the name can change without losing any of them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    // Only the hash of the token is kept, a leaked accounts file can't be used to submit scores
+    pub token_hash: String,
+}
+
+// What the client sends to /register and /rename
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Registration {
+    pub name: String,
+}
+
+// What /register hands out. The client keeps it in account.json next to username.txt and sends
+// the token along as "Authorization: Bearer <token>"
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Credentials {
+    pub id: String,
+    pub name: String,
+    pub token: String,
+}
+
+pub const MAX_NAME_LENGTH: usize = 32;
+
+// What a name has to be like, the client checks it too before asking the server
+pub fn check_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("The name is empty");
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err("The name is too long");
+    }
+    Ok(())
+}
+
+fn random_hex() -> String {
+    rand::random::<[u8; 32]>().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hash_token(token: &str) -> String {
+    sha256(token.as_bytes())
+}
+
+pub fn new_account(name: &str) -> (Account, Credentials) {
+    let id = random_hex()[..16].to_owned();
+    let token = random_hex();
+    let account = Account {
+        id: id.clone(),
+        name: name.to_owned(),
+        token_hash: hash_token(&token),
+    };
+    (account, Credentials { id, name: name.to_owned(), token })
+}
+
+// Every account, by id
+#[derive(Default, Debug)]
+pub struct Accounts {
+    accounts: HashMap<String, Account>,
+}
+
+impl Accounts {
+    // Replaces the account with the same id if there is one, that's how renames are stored
+    pub fn insert(&mut self, account: Account) {
+        self.accounts.insert(account.id.clone(), account);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Account> {
+        self.accounts.get(id)
+    }
+
+    pub fn by_token(&self, token: &str) -> Option<&Account> {
+        let hash = hash_token(token);
+        self.accounts.values().find(|a| a.token_hash == hash)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Account> {
+        self.accounts.values().find(|a| a.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn name_taken(&self, name: &str) -> bool {
+        self.by_name(name).is_some()
+    }
+}
@@ -0,0 +1,44 @@
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, env, fs, path::Path};
+
+// Which version of every asset is the current one, as asset name -> sha256 of its contents.
+// The client compares it against its cache to know what to download again
+pub type Manifest = BTreeMap<String, String>;
+
+pub fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+// Hashes every PNG in ASSETS_DIR (defaults to "assets")
+pub fn load_manifest() -> Manifest {
+    load_manifest_from(env::var("ASSETS_DIR").unwrap_or("assets".to_owned()))
+}
+
+pub fn load_manifest_from(dir: impl AsRef<Path>) -> Manifest {
+    let dir = dir.as_ref();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Couldn't read the assets directory {} ({})", dir.display(), e);
+            return Manifest::new();
+        }
+    };
+
+    let mut manifest = Manifest::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("png") {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        match fs::read(&path) {
+            Ok(bytes) => {
+                manifest.insert(name.to_owned(), sha256(&bytes));
+            }
+            Err(e) => eprintln!("Skipping asset {} ({})", path.display(), e),
+        }
+    }
+    manifest
+}
@@ -1,103 +1,563 @@
-use std::{env, fs::{create_dir_all, read_to_string, File}, io::Write, path::Path};
-use serde_json::json;
+use std::{collections::HashMap, fs::{self, create_dir_all, read_to_string, File}, io::Write, path::Path, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver, Sender, TryRecvError}, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
 use macroquad::prelude::*;
 use minreq::{ get, post };
+use serde::de::DeserializeOwned;
+
+use hardest_game_ever::{
+    accounts::{self, Credentials, Registration},
+    assets::{sha256, Manifest},
+    community::{CommunityLevelInfo, Upload},
+    data_dir,
+    editor::{delete, drag, editor_level_id, handles, parse_editor_level, pick, rect_between, snap, to_point, to_vec, EditorLevel, Handle, Object, CHECKPOINT_WIDTH, FINISH_SQUARE, GRID},
+    settings::{KeyBindings, Settings},
+    stats::{PlayerStats, Progress},
+    geometry::point_in_polygon,
+    levels::{LevelFile, LevelInfo, DEFAULT_LEVEL},
+    replay::{Replay, REPLAY_VERSION},
+    router::{self, Metric},
+    sim::{ticks_to_seconds, Checkpoint, Hitbox, Input, Player, PolygonHitbox, Portal, Simulation, Snapshot, SpeedPortal, Step, World, PLAYER_SPEED, TICK_RATE},
+    world::{self, LevelError, PlayerSize, PortalKind},
+};
+
+// The assets ship inside the binary so the game works without internet, the website only has the
+// final say if it can be reached
+fn bundled_image(name: &str) -> &'static [u8] {
+    match name {
+        "player.png" => include_bytes!("../assets/player.png"),
+        "wall.png" => include_bytes!("../assets/wall.png"),
+        "movingplatform.png" => include_bytes!("../assets/movingplatform.png"),
+        "speedportal.png" => include_bytes!("../assets/speedportal.png"),
+        "setspeedportal.png" => include_bytes!("../assets/setspeedportal.png"),
+        "gravityportal.png" => include_bytes!("../assets/gravityportal.png"),
+        "sizeportal.png" => include_bytes!("../assets/sizeportal.png"),
+        "mirrorportal.png" => include_bytes!("../assets/mirrorportal.png"),
+        "buttons.png" => include_bytes!("../assets/buttons.png"),
+        "minibuttons.png" => include_bytes!("../assets/minibuttons.png"),
+        _ => panic!("There is no bundled image called {}", name),
+    }
+}
+
+// Don't keep someone on a plane staring at a loading screen
+const NETWORK_TIMEOUT: u64 = 5;
+// How many more times a request is tried if the server can't be reached, waiting twice as long
+// every time starting from RETRY_DELAY
+const RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// Whether the last request couldn't get through at all. The classic level doesn't wait for the
+// server once it's known to be out of reach
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+// Sends the request, trying again if the server can't be reached or has a problem of its own.
+// Anything else the server answers with is up to the caller
+fn send(request: minreq::Request) -> Result<minreq::Response, String> {
+    send_retrying(request, RETRIES)
+}
+
+// Like `send`, for requests that are only worth trying `retries` more times
+fn send_retrying(request: minreq::Request, retries: u32) -> Result<minreq::Response, String> {
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        let result = request.clone().with_timeout(NETWORK_TIMEOUT).send();
+        OFFLINE.store(result.is_err(), Ordering::Relaxed);
+        let error = match result {
+            Ok(response) if response.status_code < 500 => return Ok(response),
+            Ok(response) => format!("The server had a problem ({})", response.status_code),
+            Err(e) => format!("Couldn't reach the server ({})", e),
+        };
+        if attempt == retries {
+            return Err(error);
+        }
+        attempt += 1;
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
 
-mod router;
+// GETs `path` from the server and parses it
+fn get_json<T: DeserializeOwned>(server_url: &str, path: &str, retries: u32) -> Result<T, String> {
+    let response = send_retrying(get(format!("{}{}", server_url, path)), retries)?;
+    let body = response.as_str().map_err(|e| e.to_string())?;
+    if response.status_code != 200 {
+        return Err(format!("{} ({})", body, response.status_code));
+    }
+    serde_json::from_str(body).map_err(|e| format!("The server sent something weird ({})", e))
+}
 
-#[cfg(not(debug_assertions))]
-const SERVER_URL: &str = "https://hardest-game-ever-d2ht.shuttle.app";
+// Messages that pop up in the corner for a few seconds. Anything, on any thread, can add one
+static TOASTS: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
+const TOAST_DURATION: Duration = Duration::from_secs(4);
 
-#[cfg(debug_assertions)]
-const SERVER_URL: &str = "http://127.0.0.1:3000";
+fn toast(message: impl Into<String>) {
+    let message = message.into();
+    eprintln!("{}", message);
+    TOASTS.lock().unwrap().push((message, Instant::now()));
+}
 
-fn get_image(path: &str) -> Texture2D {
-    let response = get(format!("https://muhtasim-rasheed.github.io/hardest-game-ever/assets/{}", path))
-        .send()
-        .unwrap();
-    if response.status_code == 200 {
-        let image = Texture2D::from_file_with_format(
-            &response.as_bytes(),
-            Some(ImageFormat::Png),
-        );
-        image.set_filter(FilterMode::Nearest);
-        return image;
-    } else {
-        panic!("Failed to load image as response code was not 200 ({}).", response.status_code);
+// Every screen calls this right before `next_frame`
+fn draw_toasts() {
+    let mut toasts = TOASTS.lock().unwrap();
+    toasts.retain(|(_, shown)| shown.elapsed() < TOAST_DURATION);
+    set_default_camera();
+    for (i, (message, _)) in toasts.iter().rev().enumerate() {
+        let width = measure_text(message, None, 24, 1.0).width + 24.0;
+        let y = screen_height() - 60.0 - i as f32 * 44.0;
+        draw_rectangle(screen_width() - width - 20.0, y, width, 36.0, Color::new(0.25, 0.05, 0.05, 0.9));
+        draw_text(message, screen_width() - width - 8.0, y + 25.0, 24.0, WHITE);
     }
 }
 
-fn submit_score(username: &str, score: u32) {
-    if score == 0 {
-        return;
+type Job = Box<dyn FnOnce() + Send>;
+
+// Everything that goes over the network happens on these threads, one job after the other, so the
+// window never waits for the server. Each job hands its result back through its own `Pending`.
+// What the player is waiting on has its own thread, so it never queues up behind the asset refresh
+// or the outbox
+#[derive(Clone)]
+struct Network {
+    foreground: Sender<Job>,
+    background: Sender<Job>,
+}
+
+fn network_thread() -> Sender<Job> {
+    let (jobs, queue) = channel::<Job>();
+    thread::spawn(move || {
+        for job in queue {
+            job();
+        }
+    });
+    jobs
+}
+
+impl Network {
+    fn new() -> Network {
+        Network { foreground: network_thread(), background: network_thread() }
     }
-    // Get the username from the PC
-    // let username = env::var("USERNAME").unwrap_or("Player".to_owned());
-    // No longer needed as the username is passed as an argument
 
-    // let request = post("https://hardest-game-ever-d2ht.shuttle.app/submit")
-    let request = post(format!("{}/submit", SERVER_URL))
-        .with_header("Content-Type", "application/json")
-        .with_body(serde_json::to_string(&router::Score {
-            player: username.to_owned(),
-            score: score as u32,
-        }).unwrap())
+    // For things the player is waiting on
+    fn run<T: Send + 'static>(&self, job: impl FnOnce() -> Result<T, String> + Send + 'static) -> Pending<T> {
+        Network::queue(&self.foreground, job)
+    }
+
+    // For things that can take as long as they need, like refreshing assets and sending scores
+    fn run_in_background<T: Send + 'static>(&self, job: impl FnOnce() -> Result<T, String> + Send + 'static) -> Pending<T> {
+        Network::queue(&self.background, job)
+    }
+
+    fn queue<T: Send + 'static>(jobs: &Sender<Job>, job: impl FnOnce() -> Result<T, String> + Send + 'static) -> Pending<T> {
+        let (sender, receiver) = channel();
+        let _ = jobs.send(Box::new(move || {
+            let _ = sender.send(job());
+        }));
+        Pending { receiver }
+    }
+
+    // Fetches and parses `path`
+    fn get_json<T: DeserializeOwned + Send + 'static>(&self, server_url: &str, path: String) -> Pending<T> {
+        let server_url = server_url.to_owned();
+        self.run(move || get_json(&server_url, &path, RETRIES))
+    }
+}
+
+// The result of a job on the network thread, check on it once a frame
+struct Pending<T> {
+    receiver: Receiver<Result<T, String>>,
+}
+
+enum Loading<T> {
+    Waiting,
+    Done(T),
+    Failed(String),
+}
+
+impl<T> Pending<T> {
+    // It's only Done or Failed once, after that it's Failed
+    fn poll(&self) -> Loading<T> {
+        match self.receiver.try_recv() {
+            Ok(Ok(result)) => Loading::Done(result),
+            Ok(Err(e)) => Loading::Failed(e),
+            Err(TryRecvError::Disconnected) => Loading::Failed("The request got lost".to_owned()),
+            Err(TryRecvError::Empty) => Loading::Waiting,
+        }
+    }
+}
+
+// Shows `message` until the job is done, None if the player didn't want to wait
+async fn wait_for<T>(pending: Pending<T>, message: &str, keys: &Keys) -> Option<Result<T, String>> {
+    // Don't let the key press that got here also skip the waiting
+    next_frame().await;
+
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        match pending.poll() {
+            Loading::Done(result) => return Some(Ok(result)),
+            Loading::Failed(e) => return Some(Err(e)),
+            Loading::Waiting => {}
+        }
+        if is_key_pressed(keys.leave) {
+            return None;
+        }
+
+        let dots = ".".repeat((get_time() * 3.0) as usize % 4);
+        draw_text(&format!("{}{}", message, dots), 100.0, 100.0, 48.0, WHITE);
+        draw_text(&format!("Press {} to stop waiting", key_name(keys.leave)), 100.0, 800.0, 24.0, GRAY);
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+// Names can have anything in them, which doesn't go in a URL as is
+fn url_encode(text: &str) -> String {
+    text.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+const ASSET_NAMES: [&str; 10] = [
+    "player.png", "wall.png", "movingplatform.png", "speedportal.png", "setspeedportal.png", "gravityportal.png",
+    "sizeportal.png", "mirrorportal.png", "buttons.png", "minibuttons.png",
+];
+
+// Assets are cached as <directory>/assets/<sha256>-<name>, so a file that doesn't hash to what its
+// name says got corrupted somewhere along the way
+fn cached_asset(directory: &str, name: &str) -> Option<(String, Vec<u8>)> {
+    let entries = fs::read_dir(format!("{}/assets", directory)).ok()?;
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let Some(hash) = file_name.strip_suffix(&format!("-{}", name)) else {
+            continue;
+        };
+        match fs::read(entry.path()) {
+            Ok(bytes) if sha256(&bytes) == hash => return Some((hash.to_owned(), bytes)),
+            _ => {
+                eprintln!("The cached {} is corrupt, throwing it away", file_name);
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    None
+}
+
+fn cache_asset(directory: &str, name: &str, hash: &str, bytes: &[u8]) -> std::io::Result<()> {
+    // Its name is a promise about what's in it
+    if sha256(bytes) != hash {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("it doesn't hash to {}", hash)));
+    }
+    let assets_directory = format!("{}/assets", directory);
+    create_dir_all(&assets_directory)?;
+    // Only the newest version of each asset is kept
+    uncache_asset(directory, name)?;
+    fs::write(format!("{}/{}-{}", assets_directory, hash, name), bytes)
+}
+
+fn uncache_asset(directory: &str, name: &str) -> std::io::Result<()> {
+    for entry in fs::read_dir(format!("{}/assets", directory))?.flatten() {
+        if entry.file_name().to_string_lossy().ends_with(&format!("-{}", name)) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// What the game starts with, the cached version of an asset or the bundled one
+fn local_asset(directory: &str, name: &str) -> Vec<u8> {
+    cached_asset(directory, name).map(|(_, bytes)| bytes).unwrap_or(bundled_image(name).to_vec())
+}
+
+// Tried once, whatever doesn't download is tried again the next time the game starts. Not through
+// `send`, the assets come from somewhere else so they say nothing about whether the server is up
+fn download_asset(asset_url: &str, name: &str) -> Result<Vec<u8>, String> {
+    let response = get(format!("{}/{}", asset_url, name))
+        .with_timeout(NETWORK_TIMEOUT)
         .send()
-        .unwrap();
+        .map_err(|e| format!("Couldn't download {} ({})", name, e))?;
+    if response.status_code != 200 {
+        return Err(format!("Couldn't download {} ({})", name, response.status_code));
+    }
+    Ok(response.into_bytes())
+}
 
-    if request.status_code != 200 {
-        panic!("Failed to submit score as response code was not 200 ({}).", request.status_code);
+// Downloads the assets the manifest says are newer than the cached ones, and caches them. One that
+// can't be downloaded keeps the version the game already has, the rest are still tried
+fn refresh_assets(server_url: &str, asset_url: &str, directory: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+    // Not tried again, the bundled assets are fine until the next time the game starts
+    let manifest: Manifest = get_json(server_url, "/assets/manifest", 0)?;
+    let mut fresh = Vec::new();
+    for name in ASSET_NAMES {
+        let Some(wanted) = manifest.get(name) else {
+            continue;
+        };
+        let cached = cached_asset(directory, name);
+        if cached.as_ref().is_some_and(|(hash, _)| hash == wanted) {
+            continue;
+        }
+        // The one in the game is already the newest, the cached one is only in the way of it
+        if sha256(bundled_image(name)) == *wanted {
+            if cached.is_some() {
+                if let Err(e) = uncache_asset(directory, name) {
+                    eprintln!("Couldn't throw away the old {} ({})", name, e);
+                }
+                fresh.push((name.to_owned(), bundled_image(name).to_vec()));
+            }
+            continue;
+        }
+        let bytes = match download_asset(asset_url, name) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        // The manifest and the file can come from different places, only what the manifest
+        // vouches for gets cached or shown
+        if sha256(&bytes) != *wanted {
+            eprintln!("The downloaded {} doesn't match the manifest", name);
+            continue;
+        }
+        if let Err(e) = cache_asset(directory, name, wanted, &bytes) {
+            eprintln!("Couldn't cache {} ({})", name, e);
+        }
+        fresh.push((name.to_owned(), bytes));
     }
+    Ok(fresh)
 }
 
-const WINDOW_WIDTH: f32 = 1600.0;
-const WINDOW_HEIGHT: f32 = 900.0;
+fn make_texture(bytes: &[u8]) -> Texture2D {
+    let image = Texture2D::from_file_with_format(bytes, Some(ImageFormat::Png));
+    image.set_filter(FilterMode::Nearest);
+    image
+}
 
-const PLAYER_SPEED: f32 = 1.0;
+enum Submitted {
+    Accepted,
+    // The server didn't like it, sending it again won't change that
+    Rejected,
+    // Couldn't reach the server, try again later
+    Unreachable,
+    // The server doesn't know the account (anymore), so a new one is needed
+    LoggedOut,
+}
 
-fn round(x: f32, places: i32) -> f32 {
-    let factor = 10.0f32.powi(places);
-    (x * factor).round() / factor
+fn account_file(directory: &str) -> String {
+    format!("{}/account.json", directory)
+}
+
+// Both network threads can need the account at once, only one of them gets to register it
+static ACCOUNT: Mutex<()> = Mutex::new(());
+
+// The account scores are submitted with. It's made the first time the server can be reached, and
+// renamed whenever username.txt doesn't match it anymore
+fn get_account(server_url: &str, directory: &str, username: &str) -> Result<Credentials, String> {
+    let _account = ACCOUNT.lock().unwrap();
+    let saved: Option<Credentials> = read_to_string(account_file(directory))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok());
+    let registration = serde_json::to_string(&Registration { name: username.to_owned() }).unwrap();
+
+    let account = match saved {
+        Some(account) if account.name == username => return Ok(account),
+        Some(mut account) => {
+            let response = send(post(format!("{}/rename", server_url))
+                .with_header("Content-Type", "application/json")
+                .with_header("Authorization", format!("Bearer {}", account.token))
+                .with_body(registration))?;
+            // Keep using the old name if the new one is taken, and ask for another one
+            if response.status_code != 200 {
+                toast(format!("Couldn't rename you to {} ({})", username, response.as_str().unwrap_or("")));
+                if response.status_code == 409 {
+                    let _ = fs::remove_file(username_file(directory));
+                }
+                return Ok(account);
+            }
+            account.name = username.to_owned();
+            account
+        }
+        None => {
+            let response = send(post(format!("{}/register", server_url))
+                .with_header("Content-Type", "application/json")
+                .with_body(registration))?;
+            let body = response.as_str().map_err(|e| e.to_string())?;
+            // Trying again won't help, the game asks for another name once username.txt is gone
+            if response.status_code == 409 {
+                let _ = fs::remove_file(username_file(directory));
+                return Err(format!("Someone already has the name {}, pick another one", username));
+            }
+            if response.status_code != 200 {
+                return Err(format!("Couldn't register as {} ({})", username, body));
+            }
+            serde_json::from_str(body).map_err(|e| e.to_string())?
+        }
+    };
+
+    if let Err(e) = create_dir_all(directory).and_then(|_| fs::write(account_file(directory), serde_json::to_string(&account).unwrap())) {
+        toast(format!("Couldn't save your account ({})", e));
+    }
+    Ok(account)
+}
+
+// How a visit to a level went
+#[derive(Clone, Debug)]
+struct Session {
+    best: Replay,
+    attempts: u32,
+    // Ticks played over all the attempts
+    playtime: u32,
 }
 
-fn cross(a: Vec2, b: Vec2) -> f32 {
-    a.x * b.y - a.y * b.x
+impl Session {
+    fn merge(&mut self, other: Session) {
+        if other.best.ticks > self.best.ticks {
+            self.best = other.best;
+        }
+        self.attempts += other.attempts;
+        self.playtime += other.playtime;
+    }
 }
 
-fn line_intersect(p1: Vec2, p2: Vec2, q1: Vec2, q2: Vec2) -> bool {
-    let r = p2 - p1;
-    let s = q2 - q1;
+// A run for the server, judged on its replay. Every run is sent on its own, so it's one attempt
+// and the playtime is just how long it lasted
+fn run_submission(username: &str, replay: &Replay) -> router::Submission {
+    router::Submission {
+        player: username.to_owned(),
+        score: replay.ticks,
+        level: replay.level.clone(),
+        replay: replay.clone(),
+        attempts: 1,
+        playtime: replay.ticks,
+    }
+}
 
-    let r_cross_s = cross(r, s);
-    let q_minus_p = q1 - p1;
+fn submit_score(server_url: &str, account: &Credentials, submission: &router::Submission) -> Submitted {
+    // The server plays the replay itself to check the score is real
+    let response = send(post(format!("{}/submit", server_url))
+        .with_header("Content-Type", "application/json")
+        .with_header("Authorization", format!("Bearer {}", account.token))
+        .with_body(serde_json::to_string(submission).unwrap()));
 
-    if r_cross_s == 0.0 {
-        return false;
+    match response {
+        Ok(response) if response.status_code == 200 => Submitted::Accepted,
+        Ok(response) if response.status_code == 401 => Submitted::LoggedOut,
+        Ok(response) => {
+            toast(format!("The server didn't take a score ({})", response.as_str().unwrap_or("")));
+            Submitted::Rejected
+        }
+        Err(_) => Submitted::Unreachable,
     }
+}
+
+// Scores that couldn't be sent yet, one submission per line. The game adds to it and the network
+// thread takes out what got through, so it's only touched while holding OUTBOX
+fn outbox_file(directory: &str) -> String {
+    format!("{}/outbox.jsonl", directory)
+}
+
+static OUTBOX: Mutex<()> = Mutex::new(());
+
+fn read_outbox(directory: &str) -> Vec<String> {
+    read_to_string(outbox_file(directory))
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_owned())
+        .collect()
+}
 
-    let t = cross(q_minus_p, s) / r_cross_s;
-    let u = cross(q_minus_p, r) / r_cross_s;
+fn queue_scores(directory: &str, submissions: &[router::Submission]) -> std::io::Result<()> {
+    let _outbox = OUTBOX.lock().unwrap();
+    create_dir_all(directory)?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(outbox_file(directory))?;
+    for submission in submissions {
+        writeln!(file, "{}", serde_json::to_string(submission).unwrap())?;
+    }
+    Ok(())
+}
 
-    t >= 0.0 && t <= 1.0 && u >= 0.0 && u <= 1.0
+// How many scores got sent, and how many are still waiting in the outbox
+struct SubmitReport {
+    sent: usize,
+    queued: usize,
 }
 
-fn point_in_polygon(point: Vec2, polygon: &[Vec2]) -> bool {
-    let mut inside = false;
-    let mut j = polygon.len() - 1;
+// Sends everything in the outbox. Whatever doesn't get through stays there for next time
+fn flush_outbox(server_url: &str, username: &str, directory: &str) -> SubmitReport {
+    let pending = {
+        let _outbox = OUTBOX.lock().unwrap();
+        read_outbox(directory)
+    };
+    if pending.is_empty() {
+        return SubmitReport { sent: 0, queued: 0 };
+    }
 
-    for i in 0..polygon.len() {
-        let pi = polygon[i];
-        let pj = polygon[j];
+    // Without an account nothing can be sent, so it all waits
+    let account = get_account(server_url, directory, username).map_err(toast).ok();
+    let mut sent = 0;
+    // Lines that are done with, either sent or refused by the server
+    let mut done = Vec::new();
+    for line in &pending {
+        let Some(account) = &account else {
+            break;
+        };
+        let Ok(submission) = serde_json::from_str::<router::Submission>(line) else {
+            done.push(line);
+            continue;
+        };
+        match submit_score(server_url, account, &submission) {
+            Submitted::Accepted => {
+                sent += 1;
+                done.push(line);
+            }
+            Submitted::Rejected => done.push(line),
+            // No point in waiting for the retries on every single one
+            Submitted::Unreachable => break,
+            Submitted::LoggedOut => {
+                toast("The server forgot your account, you'll get a new one next time");
+                let _ = fs::remove_file(account_file(directory));
+                break;
+            }
+        }
+    }
 
-        if ((pi.y > point.y) != (pj.y > point.y))
-            && (point.x < (pj.x - pi.x) * (point.y - pi.y) / (pj.y - pi.y) + pi.x)
-        {
-            inside = !inside;
+    // More might have been added in the meantime, so only take out the ones that were handled
+    let _outbox = OUTBOX.lock().unwrap();
+    let mut remaining = read_outbox(directory);
+    for line in done {
+        if let Some(i) = remaining.iter().position(|l| l == line) {
+            remaining.remove(i);
         }
-        j = i;
     }
-    inside
+    let result = if remaining.is_empty() {
+        fs::remove_file(outbox_file(directory))
+    } else {
+        fs::write(outbox_file(directory), remaining.join("\n") + "\n")
+    };
+    if let Err(e) = result {
+        toast(format!("Couldn't update the scores waiting to be sent ({})", e));
+    }
+    SubmitReport { sent, queued: remaining.len() }
+}
+
+// Puts the scores in the outbox right away, so they're safe even if the game closes, then has the
+// network thread send the whole outbox
+fn send_scores(network: &Network, server_url: &str, username: &str, directory: &str, submissions: Vec<router::Submission>) -> Pending<SubmitReport> {
+    if let Err(e) = queue_scores(directory, &submissions) {
+        toast(format!("Couldn't save your scores for later ({})", e));
+    }
+    let (server_url, username, directory) = (server_url.to_owned(), username.to_owned(), directory.to_owned());
+    network.run_in_background(move || Ok(flush_outbox(&server_url, &username, &directory)))
+}
+
+const WINDOW_WIDTH: f32 = 1600.0;
+const WINDOW_HEIGHT: f32 = 900.0;
+
+fn round(x: f32, places: i32) -> f32 {
+    let factor = 10.0f32.powi(places);
+    (x * factor).round() / factor
 }
 
 fn draw_texture_across_hitbox(texture: &Texture2D, hitbox: &Hitbox) {
@@ -187,447 +647,346 @@ fn get_texture_from_spritesheet(
     Texture2D::from_image(&new_image)
 }
 
-struct Hitbox {
-    x: f32,
-    y: f32,
-    width: f32,
-    height: f32,
-    color: Color,
-}
-
-impl Hitbox {
-    fn new(x: f32, y: f32, width: f32, height: f32, color: Color) -> Hitbox {
-        Hitbox { x, y, width, height, color }
+fn world_from_json(data: &str) -> Result<world::World, LevelError> {
+    // Old servers send the world as a string containing the JSON, so unwrap it once more
+    match serde_json::from_str(data) {
+        Ok(serde_json::Value::String(inner)) => world::World::parse(&inner),
+        _ => world::World::parse(data),
     }
+}
 
-    fn collides(&self, other: &Hitbox) -> bool {
-        self.x < other.x + other.width &&
-        self.x + self.width > other.x &&
-        self.y < other.y + other.height &&
-        self.y + self.height > other.y
-    }
+enum LoadError {
+    // Couldn't get the level, and why
+    Unavailable(String),
+    Broken(LevelError),
+}
 
-    fn get_pos(&self) -> Vec2 {
-        vec2(self.x, self.y)
+fn get_world(server_url: &str, level: &str) -> Result<world::World, LoadError> {
+    // The original level is built in, so there's always something to play, and no reason to wait
+    // for a server that can't be reached
+    if level == DEFAULT_LEVEL && OFFLINE.load(Ordering::Relaxed) {
+        return Ok(world::World::classic());
     }
-
-    fn draw(&self) {
-        draw_rectangle_lines(self.x, self.y, self.width, self.height, 5.0, self.color);
+    let retries = if level == DEFAULT_LEVEL { 0 } else { RETRIES };
+    let response = send_retrying(get(format!("{}/levels/{}", server_url, url_encode(level))), retries)
+        .and_then(|response| match response.as_str() {
+            Ok(data) if response.status_code == 200 => Ok(data.to_owned()),
+            Ok(data) => Err(format!("{} ({})", data, response.status_code)),
+            Err(e) => Err(e.to_string()),
+        });
+    match response {
+        Ok(data) => world_from_json(&data).map_err(LoadError::Broken),
+        Err(_) if level == DEFAULT_LEVEL => Ok(world::World::classic()),
+        Err(e) => Err(LoadError::Unavailable(e)),
     }
 }
 
-struct PolygonHitbox {
-    points: Vec<Vec2>,
-    color: Color,
+// Loads a level on the network thread, showing a loading screen meanwhile. None if the player
+// gave up waiting or it couldn't be loaded (after saying why)
+async fn load_world(network: &Network, server_url: &str, level: &str, level_name: &str, keys: &Keys) -> Option<world::World> {
+    let (server_url, id) = (server_url.to_owned(), level.to_owned());
+    let pending = network.run(move || Ok(get_world(&server_url, &id)));
+    let error = match wait_for(pending, &format!("Loading {}", level_name), keys).await? {
+        Ok(Ok(world)) => return Some(world),
+        Ok(Err(e)) => e,
+        Err(e) => LoadError::Unavailable(e),
+    };
+    level_error(level_name, &error, keys).await;
+    None
 }
 
-impl PolygonHitbox {
-    fn new(points: Vec<Vec2>, color: Color) -> PolygonHitbox {
-        PolygonHitbox { points, color }
-    }
-
-    fn collides(&self, other: &PolygonHitbox) -> bool {
-        for i in 0..self.points.len() {
-            let p1 = self.points[i];
-            let p2 = self.points[(i + 1) % self.points.len()];
+// Only the newest replays are kept around
+const MAX_REPLAYS: usize = 100;
 
-            for j in 0..other.points.len() {
-                let q1 = other.points[j];
-                let q2 = other.points[(j + 1) % other.points.len()];
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
 
-                if line_intersect(p1, p2, q1, q2) {
-                    return true;
-                }
-            }
-        }
-        false
+// Replays are saved as <directory>/replays/<time it was recorded in ms>-<level>.json
+fn save_replay(directory: &str, replay: &Replay) {
+    if replay.ticks == 0 {
+        return;
     }
+    if let Err(e) = write_replay(directory, replay) {
+        toast(format!("Couldn't save the replay ({})", e));
+    }
+}
 
-    fn draw(&self) {
-        for i in 0..self.points.len() {
-            let p1 = self.points[i];
-            let p2 = self.points[(i + 1) % self.points.len()];
-            draw_line(p1.x, p1.y, p2.x, p2.y, 3.0, self.color);
+fn write_replay(directory: &str, replay: &Replay) -> std::io::Result<()> {
+    let replays_directory = format!("{}/replays", directory);
+    create_dir_all(&replays_directory)?;
+    let mut file = File::create(format!("{}/{}-{}.json", replays_directory, now_millis(), replay.level))?;
+    file.write_all(serde_json::to_string(replay).unwrap().as_bytes())?;
+
+    let mut names: Vec<_> = fs::read_dir(&replays_directory)?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    if names.len() > MAX_REPLAYS {
+        names.sort_by_key(|name| replay_time(name));
+        for name in &names[..names.len() - MAX_REPLAYS] {
+            let _ = fs::remove_file(format!("{}/{}", replays_directory, name));
         }
     }
+    Ok(())
 }
 
-struct MovingObject {
-    from: Vec2,
-    to: Vec2,
-    forward: bool,
-    speed: f32,
-    hitbox: Hitbox,
+fn replay_time(file_name: &str) -> u128 {
+    file_name.split('-').next().and_then(|t| t.parse().ok()).unwrap_or(0)
 }
 
-impl MovingObject {
-    fn new(from: Vec2, to: Vec2, width: f32, height: f32, speed: f32) -> MovingObject {
-        MovingObject {
-            from,
-            to,
-            forward: true,
-            speed,
-            hitbox: Hitbox::new(from.x, from.y, width, height, ORANGE),
-        }
-    }
-   
-    fn update(&mut self) {
-        let direction = self.to - self.from;
-        let distance = direction.length();
-        let normalized_direction = direction / distance;
+// Every saved replay along with when it was recorded, newest first
+fn load_replays(directory: &str) -> Vec<(u128, Replay)> {
+    let Ok(entries) = fs::read_dir(format!("{}/replays", directory)) else {
+        return Vec::new();
+    };
+    let mut replays: Vec<(u128, Replay)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let replay = serde_json::from_str(&read_to_string(entry.path()).ok()?).ok()?;
+            Some((replay_time(&entry.file_name().to_string_lossy()), replay))
+        })
+        .collect();
+    replays.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+    replays
+}
 
-        if self.forward {
-            self.hitbox.x += normalized_direction.x * self.speed;
-            self.hitbox.y += normalized_direction.y * self.speed;
-        } else {
-            self.hitbox.x -= normalized_direction.x * self.speed;
-            self.hitbox.y -= normalized_direction.y * self.speed;
-        }
+// Your fastest time to the finish line on every level, so the results can tell you when you beat it
+fn records_file(directory: &str) -> String {
+    format!("{}/records.json", directory)
+}
 
-        let current_distance = (self.hitbox.get_pos() - self.from).length();
-        
-        if current_distance >= distance {
-            self.forward = false;
-        } else if current_distance <= 0.0 {
-            self.forward = true;
-        }
-    }
+fn load_records(directory: &str) -> HashMap<String, u32> {
+    read_to_string(records_file(directory))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
 
-    fn draw(&self) {
-        self.hitbox.draw();
+fn save_records(directory: &str, records: &HashMap<String, u32>) {
+    if let Err(e) = create_dir_all(directory).and_then(|_| fs::write(records_file(directory), serde_json::to_string(records).unwrap())) {
+        toast(format!("Couldn't save your best time ({})", e));
     }
 }
 
-struct SpeedPortal {
-    used: bool,
-    speed_change: f32,
-    hitbox: Hitbox,
+// Makes every texture (and the title screen, which has the buttons) out of the images
+fn load_textures(images: &HashMap<String, Vec<u8>>) -> (Textures, TitleScreen) {
+    let texture = |name: &str| make_texture(&images[name]);
+    let textures = Textures {
+        player: texture("player.png"),
+        wall: texture("wall.png"),
+        movingplatform: texture("movingplatform.png"),
+        speedportal: texture("speedportal.png"),
+        setspeedportal: texture("setspeedportal.png"),
+        gravityportal: texture("gravityportal.png"),
+        sizeportal: texture("sizeportal.png"),
+        mirrorportal: texture("mirrorportal.png"),
+    };
+    (textures, TitleScreen::new(&texture("buttons.png"), &texture("minibuttons.png")))
 }
 
-impl SpeedPortal {
-    fn new(x: f32, y: f32, speed_change: f32) -> SpeedPortal {
-        SpeedPortal {
-            used: false,
-            speed_change,
-            hitbox: Hitbox::new(x, y, 64., 128., PURPLE),
-        }
-    }
+#[derive(Clone)]
+struct Textures {
+    player: Texture2D,
+    wall: Texture2D,
+    movingplatform: Texture2D,
+    speedportal: Texture2D,
+    setspeedportal: Texture2D,
+    gravityportal: Texture2D,
+    sizeportal: Texture2D,
+    mirrorportal: Texture2D,
+}
 
-    fn update(&mut self, player: &mut Player) {
-        if player.hitbox.collides(&self.hitbox) {
-            if self.used {
-                return;
-            }
-            player.x_speed_mult *= self.speed_change;
-            self.used = true;
-        }
+// Turns the time between frames into a whole number of ticks, keeping what's left for next frame
+struct FixedTimestep {
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    const TICK: f32 = 1.0 / TICK_RATE as f32;
+
+    fn new() -> FixedTimestep {
+        FixedTimestep { accumulator: 0.0 }
     }
 
-    fn draw(&self, texture: &Texture2D) {
-        draw_texture_ex(&texture, self.hitbox.x, self.hitbox.y, WHITE, DrawTextureParams {
-            dest_size: Some(vec2(self.hitbox.width, self.hitbox.height)),
-            ..Default::default()
-        });
+    // How many ticks to run this frame
+    fn advance(&mut self, frame_time: f32) -> u32 {
+        // After a hitch (dragging the window etc.) don't try to catch up on all of it at once
+        self.accumulator += frame_time.min(0.25);
+        let ticks = (self.accumulator / Self::TICK) as u32;
+        self.accumulator -= ticks as f32 * Self::TICK;
+        ticks
     }
-}
 
-impl Clone for Hitbox {
-    fn clone(&self) -> Hitbox {
-        Hitbox {
-            x: self.x,
-            y: self.y,
-            width: self.width,
-            height: self.height,
-            color: self.color,
-        }
+    // How far along the next tick we are, from 0 to 1
+    fn alpha(&self) -> f32 {
+        self.accumulator / Self::TICK
     }
-}
 
-impl Clone for PolygonHitbox {
-    fn clone(&self) -> PolygonHitbox {
-        PolygonHitbox {
-            points: self.points.clone(),
-            color: self.color,
-        }
+    fn reset(&mut self) {
+        self.accumulator = 0.0;
     }
 }
 
-impl Clone for MovingObject {
-    fn clone(&self) -> MovingObject {
-        MovingObject {
-            from: self.from,
-            to: self.to,
-            forward: self.forward,
-            speed: self.speed,
-            hitbox: self.hitbox.clone(),
-        }
+// The player and world somewhere between two ticks, so motion is smooth when there are more
+// frames than ticks
+fn interpolate(previous: &Simulation, current: &Simulation, alpha: f32) -> (Player, World) {
+    let lerp = |a: f32, b: f32| a + (b - a) * alpha;
+
+    let mut player = current.player.clone();
+    player.x = lerp(previous.player.x, current.player.x);
+    player.y = lerp(previous.player.y, current.player.y);
+    player.vy = lerp(previous.player.vy, current.player.vy);
+    player.hitbox.x = player.x;
+    player.hitbox.y = player.y;
+
+    let mut world = current.world.clone();
+    for (object, previous) in world.moving_objects.iter_mut().zip(&previous.world.moving_objects) {
+        object.hitbox.x = lerp(previous.hitbox.x, object.hitbox.x);
+        object.hitbox.y = lerp(previous.hitbox.y, object.hitbox.y);
     }
-}
 
-enum Objects {
-    Hitbox(Hitbox),
-    PolygonHitbox(PolygonHitbox),
-    MovingObject(MovingObject),
-    SpeedPortal(SpeedPortal),
+    (player, world)
 }
 
-struct Player {
-    x: f32,
-    y: f32,
-    vy: f32,
-    is_facing_up: bool,
-    x_speed_mult: f32,
-    hitbox: Hitbox,
+fn draw_player(player: &Player, texture: &Texture2D) {
+    // Facing the other way when mirrored, and upside down when gravity is
+    let rotation = ((player.vy * 20.0) / (PLAYER_SPEED * player.x_speed_mult)).to_radians();
+    draw_texture_ex(texture, player.x - player.hitbox.width / 1.5 + player.hitbox.width / 2., player.y - player.hitbox.height / 1.5 + player.hitbox.height / 2., WHITE, DrawTextureParams { // One line of goddamn code
+        dest_size: Some(vec2(player.hitbox.width * 1.5, player.hitbox.height * 1.5)),
+        rotation: if player.mirrored { -rotation } else { rotation },
+        flip_x: player.mirrored,
+        flip_y: player.gravity_inverted,
+        ..Default::default()
+    });
 }
 
-impl Player {
-    fn new(x: f32, y: f32) -> Player {
-        // let texture = load_texture("assets/player.png").await.unwrap();
-        // texture.set_filter(FilterMode::Nearest);
-        Player {
-            x,
-            y,
-            vy: 0.0,
-            is_facing_up: true, // Start out flying because the click from the titlescreen persists
-            x_speed_mult: 3.5,
-            hitbox: Hitbox::new(x, y, 32.0, 24.0, RED),
-        }
+// Where the camera goes, with the player a bit behind the middle so there's more of what's coming
+fn camera_x(player: &Player) -> f32 {
+    if player.mirrored {
+        player.x - 200.
+    } else {
+        player.x + 200.
     }
-   
-    fn update(&mut self, world: &World) -> bool {
-        if world.player_hit_check(self) {
-            return true; // Player dies
-        }
-
-        // Movement logic only runs if no collision
-        if is_mouse_button_pressed(MouseButton::Left) || is_key_pressed(KeyCode::Space) {
-            self.is_facing_up = !self.is_facing_up;
-        }
-
-        if self.is_facing_up {
-            self.vy -= PLAYER_SPEED;
-        } else {
-            self.vy += PLAYER_SPEED;
-        }
-
-        self.y += self.vy;
-        self.hitbox.y = self.y;
-
-        self.x += PLAYER_SPEED * self.x_speed_mult;
-        self.hitbox.x = self.x;
-
-        self.vy *= 0.9;
+}
 
-        false // Player survives
-    }
+fn draw_speed_portal(portal: &SpeedPortal, texture: &Texture2D) {
+    draw_texture_ex(texture, portal.hitbox.x, portal.hitbox.y, WHITE, DrawTextureParams {
+        dest_size: Some(vec2(portal.hitbox.width, portal.hitbox.height)),
+        ..Default::default()
+    });
+}
 
-    fn draw(&self, texture: &Texture2D) {
-        draw_texture_ex(&texture, self.x - self.hitbox.width / 1.5 + self.hitbox.width / 2., self.y - self.hitbox.height / 1.5 + self.hitbox.height / 2., WHITE, DrawTextureParams { // One line of goddamn code
-            dest_size: Some(vec2(self.hitbox.width * 1.5, self.hitbox.height * 1.5)),
-            rotation: ((self.vy * 20.0) / (PLAYER_SPEED * self.x_speed_mult)).to_radians(),
+// The ones that put something back to normal are greyed out. Size portals show how big the player
+// is going to be
+fn draw_portal(portal: &Portal, textures: &Textures) {
+    let (texture, back_to_normal) = match portal.kind {
+        PortalKind::Gravity { inverted } => (&textures.gravityportal, !inverted),
+        PortalKind::Size { size } => (&textures.sizeportal, size == PlayerSize::Normal),
+        PortalKind::Mirror { mirrored } => (&textures.mirrorportal, !mirrored),
+        PortalKind::Speed { .. } => (&textures.setspeedportal, false),
+    };
+    let color = if back_to_normal { GRAY } else { WHITE };
+    let hitbox = &portal.hitbox;
+    draw_texture_ex(texture, hitbox.x, hitbox.y, color, DrawTextureParams {
+        dest_size: Some(vec2(hitbox.width, hitbox.height)),
+        ..Default::default()
+    });
+    if let PortalKind::Size { size } = portal.kind {
+        let (width, height) = (world::PLAYER_WIDTH * size.scale(), world::PLAYER_HEIGHT * size.scale());
+        draw_texture_ex(&textures.player, hitbox.x + (hitbox.width - width) / 2.0, hitbox.y + (hitbox.height - height) / 2.0, Color::new(1.0, 1.0, 1.0, 0.6), DrawTextureParams {
+            dest_size: Some(vec2(width, height)),
             ..Default::default()
         });
     }
 }
 
-struct World {
-    objects: Vec<Hitbox>,
-    poly_objects: Vec<PolygonHitbox>,
-    moving_objects: Vec<MovingObject>,
-    speed_increases: Vec<SpeedPortal>,
+// Checkered, and all the way up and down
+fn draw_finish_line(x: f32) {
+    for i in -64..64 {
+        let y = i as f32 * FINISH_SQUARE;
+        let (first, second) = if i % 2 == 0 { (WHITE, BLACK) } else { (BLACK, WHITE) };
+        draw_rectangle(x, y, FINISH_SQUARE, FINISH_SQUARE, first);
+        draw_rectangle(x + FINISH_SQUARE, y, FINISH_SQUARE, FINISH_SQUARE, second);
+    }
 }
 
-impl World {
-    fn new() -> World {
-        World {
-            objects: vec![
-                Hitbox::new(0.0, 250.0, 5000.0, 50.0, GREEN),
-                Hitbox::new(0.0, -300.0, 5000.0, 50.0, GREEN),
-
-                Hitbox::new(500.0, 15.0, 50.0, 235.0, GREEN),
-                Hitbox::new(625.0, -250.0, 50.0, 235.0, GREEN),
-            ],
-            poly_objects: vec![
-                PolygonHitbox::new(vec![
-                    vec2(775.0, -80.0), // Top left
-                    vec2(775.0, 250.0), // Bottom left
-                    vec2(1075.0, 250.0), // Bottom right
-                    vec2(1075.0, 0.0), // Top right (1)
-                    vec2(975.0, -80.0), // Top right (2)
-                ], GREEN),
-            ],
-            moving_objects: vec![
-                MovingObject::new(vec2(1100.0, -225.0), vec2(1200.0, 150.0), 50.0, 100.0, 3.0),
-                MovingObject::new(vec2(1300.0, 150.0), vec2(1400.0, -225.0), 50.0, 100.0, 3.0),
-                MovingObject::new(vec2(1500.0, 0.0), vec2(1600.0, 0.0), 100.0, 50.0, 3.0),
-            ],
-            speed_increases: vec![
-                SpeedPortal::new(1075.0, -200.0, 2.0),
-            ],
-        }
+// Dashed, green once the player got past it
+fn draw_checkpoint_line(checkpoint: &Checkpoint) {
+    let color = if checkpoint.reached { GREEN } else { Color::new(0.0, 0.9, 0.3, 0.35) };
+    for i in -32..32 {
+        draw_rectangle(checkpoint.x, i as f32 * CHECKPOINT_DASH * 2.0, CHECKPOINT_WIDTH, CHECKPOINT_DASH, color);
     }
+}
 
-    fn from_json(data_unprocessed: &str) -> World {
-        // Make sure there is no " before and after the root object and replace all the "\" with ""
-        let data = data_unprocessed.trim_matches('"').replace("\\", "");
-        let world: serde_json::Value = serde_json::from_str(data.as_str()).unwrap();
-        let mut objects = Vec::new();
-        let mut poly_objects = Vec::new();
-        let mut moving_objects = Vec::new();
-        let mut speed_increases = Vec::new();
-        for object in world["objects"].as_array().unwrap() {
-            objects.push(Hitbox::new(
-                object["x"].as_f64().unwrap() as f32,
-                object["y"].as_f64().unwrap() as f32,
-                object["width"].as_f64().unwrap() as f32,
-                object["height"].as_f64().unwrap() as f32,
-                GREEN,
-            ));
-        }
-        for object in world["poly_objects"].as_array().unwrap() {
-            let mut points = Vec::new();
-            for point in object["points"].as_array().unwrap() {
-                points.push(vec2(
-                    point["x"].as_f64().unwrap() as f32,
-                    point["y"].as_f64().unwrap() as f32,
-                ));
-            }
-            poly_objects.push(PolygonHitbox::new(points, GREEN));
-        }
-        for object in world["moving_objects"].as_array().unwrap() {
-            moving_objects.push(MovingObject::new(
-                vec2(
-                    object["from"]["x"].as_f64().unwrap() as f32,
-                    object["from"]["y"].as_f64().unwrap() as f32,
-                ),
-                vec2(
-                    object["to"]["x"].as_f64().unwrap() as f32,
-                    object["to"]["y"].as_f64().unwrap() as f32,
-                ),
-                object["width"].as_f64().unwrap() as f32,
-                object["height"].as_f64().unwrap() as f32,
-                object["speed"].as_f64().unwrap() as f32,
-            ));
-        }
-        for object in world["speed_increases"].as_array().unwrap() {
-            speed_increases.push(SpeedPortal::new(
-                object["x"].as_f64().unwrap() as f32,
-                object["y"].as_f64().unwrap() as f32,
-                object["speed_change"].as_f64().unwrap() as f32,
-            ));
-        }
-        World {
-            objects,
-            poly_objects,
-            moving_objects,
-            speed_increases,
-        }
-    }
-
-    fn as_json(&self) -> String {
-        let mut objects = Vec::new();
-        let mut poly_objects = Vec::new();
-        let mut moving_objects = Vec::new();
-        let mut speed_increases = Vec::new();
-        for object in &self.objects {
-            objects.push(json!({
-                "x": object.x,
-                "y": object.y,
-                "width": object.width,
-                "height": object.height,
-            }));
-        }
-        for object in &self.poly_objects {
-            let mut points = Vec::new();
-            for point in &object.points {
-                points.push(json!({
-                    "x": point.x,
-                    "y": point.y,
-                }));
-            }
-            poly_objects.push(json!({
-                "points": points,
-            }));
-        }
-        for object in &self.moving_objects {
-            moving_objects.push(json!({
-                "from": {
-                    "x": object.from.x,
-                    "y": object.from.y,
-                },
-                "to": {
-                    "x": object.to.x,
-                    "y": object.to.y,
-                },
-                "width": object.hitbox.width,
-                "height": object.hitbox.height,
-                "speed": object.speed,
-            }));
-        }
-        for object in &self.speed_increases {
-            speed_increases.push(json!({
-                "x": object.hitbox.x,
-                "y": object.hitbox.y,
-                "speed_change": object.speed_change,
-            }));
-        }
-        json!({
-            "objects": objects,
-            "poly_objects": poly_objects,
-            "moving_objects": moving_objects,
-            "speed_increases": speed_increases,
-        }).to_string()
-    }
-
-    fn player_hit_check(&self, player: &Player) -> bool {
-        for object in &self.objects {
-            if player.hitbox.collides(object) {
-                return true;
-            }
-        }
-        for object in &self.poly_objects {
-            if object.collides(&PolygonHitbox::new(vec![
-                vec2(player.x, player.y),
-                vec2(player.x + player.hitbox.width, player.y),
-                vec2(player.x + player.hitbox.width, player.y + player.hitbox.height),
-                vec2(player.x, player.y + player.hitbox.height),
-            ], RED)) {
-                return true;
-            }
-        }
-        for object in &self.moving_objects {
-            if player.hitbox.collides(&object.hitbox) {
-                return true;
-            }
-        }
-        false
-    }
+const CHECKPOINT_DASH: f32 = 16.0;
 
-    fn update(&mut self, player: &mut Player) {
-        for object in &mut self.moving_objects {
-            object.update();
-        }
+// Where a checkpoint dropped in practice mode puts the player back
+fn draw_dropped_checkpoint(player: &Player) {
+    let center = vec2(player.x + player.hitbox.width / 2.0, player.y + player.hitbox.height / 2.0);
+    draw_poly(center.x, center.y, 4, 12.0, 0.0, GREEN);
+}
 
-        for object in &mut self.speed_increases {
-            object.update(player);
-        }
+fn draw_world(world: &World, textures: &Textures) {
+    for object in &world.objects {
+        draw_texture_across_hitbox(&textures.wall, object);
+    }
+    for object in &world.poly_objects {
+        draw_texture_across_polygon(&textures.wall, object);
+    }
+    for object in &world.moving_objects {
+        draw_texture_across_hitbox(&textures.movingplatform, &object.hitbox);
+    }
+    for object in &world.speed_increases {
+        draw_speed_portal(object, &textures.speedportal);
+    }
+    for object in &world.portals {
+        draw_portal(object, textures);
     }
+    for checkpoint in &world.checkpoints {
+        draw_checkpoint_line(checkpoint);
+    }
+    if let Some(x) = world.finish {
+        draw_finish_line(x);
+    }
+}
 
-    async fn draw(&self, wall: &Texture2D, movingplatform: &Texture2D, speedportal: &Texture2D) {
-        for object in &self.objects {
-            // object.draw();
-            draw_texture_across_hitbox(&wall, object);
-        }
-        for object in &self.poly_objects {
-            // object.draw();
-            draw_texture_across_polygon(&wall, object);
-        }
-        for object in &self.moving_objects {
-            // object.draw();
-            draw_texture_across_hitbox(&movingplatform, &object.hitbox);
-        }
-        for object in &self.speed_increases {
-            object.draw(&speedportal);
+// The keys that can be bound to something, by the name they have in the settings file
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Space", KeyCode::Space), ("Enter", KeyCode::Enter), ("Escape", KeyCode::Escape), ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace), ("LeftShift", KeyCode::LeftShift), ("RightShift", KeyCode::RightShift),
+    ("LeftControl", KeyCode::LeftControl), ("RightControl", KeyCode::RightControl),
+    ("Up", KeyCode::Up), ("Down", KeyCode::Down), ("Left", KeyCode::Left), ("Right", KeyCode::Right),
+    ("A", KeyCode::A), ("B", KeyCode::B), ("C", KeyCode::C), ("D", KeyCode::D), ("E", KeyCode::E),
+    ("F", KeyCode::F), ("G", KeyCode::G), ("H", KeyCode::H), ("I", KeyCode::I), ("J", KeyCode::J),
+    ("K", KeyCode::K), ("L", KeyCode::L), ("M", KeyCode::M), ("N", KeyCode::N), ("O", KeyCode::O),
+    ("P", KeyCode::P), ("Q", KeyCode::Q), ("R", KeyCode::R), ("S", KeyCode::S), ("T", KeyCode::T),
+    ("U", KeyCode::U), ("V", KeyCode::V), ("W", KeyCode::W), ("X", KeyCode::X), ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z), ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3), ("4", KeyCode::Key4), ("5", KeyCode::Key5), ("6", KeyCode::Key6),
+    ("7", KeyCode::Key7), ("8", KeyCode::Key8), ("9", KeyCode::Key9),
+];
+
+fn key_code(name: &str) -> Option<KeyCode> {
+    KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, key)| *key)
+}
+
+fn key_name(key: KeyCode) -> &'static str {
+    KEY_NAMES.iter().find(|(_, k)| *k == key).map(|(name, _)| *name).unwrap_or("?")
+}
+
+// The key bindings from the settings, with the default for any key name that isn't known
+#[derive(Clone, Copy)]
+struct Keys {
+    jump: KeyCode,
+    leave: KeyCode,
+}
+
+impl Keys {
+    fn new(bindings: &KeyBindings) -> Keys {
+        Keys {
+            jump: key_code(&bindings.jump).unwrap_or(KeyCode::Space),
+            leave: key_code(&bindings.leave).unwrap_or(KeyCode::Escape),
         }
     }
 }
@@ -668,11 +1027,9 @@ impl TextBox {
                     self.cursor_pos -= 1;
                     self.cooldown = 5;
                 }
-            } else if is_key_down(KeyCode::Right) {
-                if self.cursor_pos < self.text.len() as u32 {
-                    self.cursor_pos += 1;
-                    self.cooldown = 5;
-                }
+            } else if is_key_down(KeyCode::Right) && self.cursor_pos < self.text.len() as u32 {
+                self.cursor_pos += 1;
+                self.cooldown = 5;
             }
         }
 
@@ -685,7 +1042,8 @@ impl TextBox {
         }
 
         while let Some(c) = get_char_pressed() {
-            if operation_used {
+            // Tab, enter and such aren't text
+            if operation_used || c.is_control() {
                 continue;
             }
             self.text.insert(self.cursor_pos.try_into().unwrap(), c);
@@ -695,7 +1053,7 @@ impl TextBox {
 
     fn draw(&self, x: f32, y: f32) {
         let text_before_cursor = &self.text[..self.cursor_pos as usize];
-        let text_width = measure_text(&text_before_cursor, None, 36, 1.0).width;
+        let text_width = measure_text(text_before_cursor, None, 36, 1.0).width;
         draw_text(&self.text, x + 10.0, y, 36.0, WHITE);
         draw_line(x + text_width + 12.0, y - 18.0, x + text_width + 12.0, y, 3.0, WHITE);
         if self.text.is_empty() {
@@ -721,7 +1079,7 @@ impl Button {
         Button {
             x,
             y,
-            hitbox: Hitbox::new(x, y, 256.0, 64.0, BLUE),
+            hitbox: Hitbox::new(x, y, 256.0, 64.0),
             texture,
             hover_texture,
             is_hovered: false,
@@ -731,18 +1089,9 @@ impl Button {
 
     fn update(&mut self) -> bool {
         let mouse_pos = mouse_position();
-        if self.hitbox.collides(&Hitbox::new(mouse_pos.0, mouse_pos.1, 1.0, 1.0, BLUE)) {
-            self.is_hovered = true;
-        } else {
-            self.is_hovered = false;
-        }
+        self.is_hovered = self.hitbox.collides(&Hitbox::new(mouse_pos.0, mouse_pos.1, 1.0, 1.0));
 
-        if self.is_hovered {
-            if is_mouse_button_pressed(MouseButton::Left) {
-                return true;
-            }
-        }
-        false
+        self.is_hovered && is_mouse_button_pressed(MouseButton::Left)
     }
 
     fn draw(&self) {
@@ -777,7 +1126,7 @@ impl MiniButton {
         MiniButton {
             x,
             y,
-            hitbox: Hitbox::new(x, y, 64.0, 64.0, BLUE),
+            hitbox: Hitbox::new(x, y, 64.0, 64.0),
             texture,
             hover_texture,
             is_hovered: false,
@@ -787,18 +1136,9 @@ impl MiniButton {
 
     fn update(&mut self) -> bool {
         let mouse_pos = mouse_position();
-        if self.hitbox.collides(&Hitbox::new(mouse_pos.0, mouse_pos.1, 1.0, 1.0, BLUE)) {
-            self.is_hovered = true;
-        } else {
-            self.is_hovered = false;
-        }
+        self.is_hovered = self.hitbox.collides(&Hitbox::new(mouse_pos.0, mouse_pos.1, 1.0, 1.0));
 
-        if self.is_hovered {
-            if is_mouse_button_pressed(MouseButton::Left) {
-                return true;
-            }
-        }
-        false
+        self.is_hovered && is_mouse_button_pressed(MouseButton::Left)
     }
 
     fn draw(&self) {
@@ -816,43 +1156,75 @@ impl MiniButton {
     }
 }
 
-struct TitleScreen {
-    title: String,
-    buttons: Vec<Button>,
-    mini_buttons: Vec<MiniButton>,
-}
-
-impl TitleScreen {
+struct TextButton {
+    hitbox: Hitbox,
+    text: String,
+    is_hovered: bool,
+    id: String,
+}
+
+impl TextButton {
+    fn new(x: f32, y: f32, width: f32, text: String, id: String) -> TextButton {
+        TextButton {
+            hitbox: Hitbox::new(x, y, width, 48.0),
+            text,
+            is_hovered: false,
+            id,
+        }
+    }
+
+    fn update(&mut self) -> bool {
+        let mouse_pos = mouse_position();
+        self.is_hovered = self.hitbox.collides(&Hitbox::new(mouse_pos.0, mouse_pos.1, 1.0, 1.0));
+
+        self.is_hovered && is_mouse_button_pressed(MouseButton::Left)
+    }
+
+    fn draw(&self, selected: bool) {
+        let color = if self.is_hovered || selected { GOLD } else { WHITE };
+        draw_rectangle_lines(self.hitbox.x, self.hitbox.y, self.hitbox.width, self.hitbox.height, 3.0, color);
+        draw_text(&self.text, self.hitbox.x + 12.0, self.hitbox.y + 34.0, 36.0, color);
+    }
+}
+
+struct TitleScreen {
+    title: String,
+    buttons: Vec<Button>,
+    mini_buttons: Vec<MiniButton>,
+    text_buttons: Vec<TextButton>,
+}
+
+impl TitleScreen {
     fn new(buttons_texture: &Texture2D, minibuttons_texture: &Texture2D) -> TitleScreen {
         // All buttons are stored inside assets/buttons.png. The first button is at (0, 0) and is
         // 128x32 pixels. The second button is at (0, 32) and is also 128x32 pixels. etc. etc. etc.
         let new_game_button = Button::new(100.0, 300.0, get_texture_from_spritesheet(
-            &buttons_texture,
+            buttons_texture,
             0, 0, 128, 32,
         ), get_texture_from_spritesheet(
-            &buttons_texture,
+            buttons_texture,
             0, 32, 128, 32,
         ), "new_game".to_owned());
         let statistics_button = Button::new(100.0, 370.0, get_texture_from_spritesheet(
-            &buttons_texture,
+            buttons_texture,
             0, 64, 128, 32,
         ), get_texture_from_spritesheet(
-            &buttons_texture,
+            buttons_texture,
             0, 96, 128, 32,
         ), "statistics".to_owned());
-        // let settings_button = Button::new(100.0, 440.0, get_texture_from_spritesheet(
-        //     &buttons_texture,
-        //     0, 128, 128, 32,
-        // ), get_texture_from_spritesheet(
-        //     &buttons_texture,
-        //     0, 160, 128, 32,
-        // ), "settings".to_owned());
+        let settings_button = Button::new(100.0, 440.0, get_texture_from_spritesheet(
+            buttons_texture,
+            0, 128, 128, 32,
+        ), get_texture_from_spritesheet(
+            buttons_texture,
+            0, 160, 128, 32,
+        ), "settings".to_owned());
 
         let leader_board_button = MiniButton::new(360.0, 300.0, get_texture_from_spritesheet(
-            &minibuttons_texture,
+            minibuttons_texture,
             0, 0, 32, 32,
         ), get_texture_from_spritesheet(
-            &minibuttons_texture,
+            minibuttons_texture,
             0, 32, 32, 32,
         ), "leader_board".to_owned());
         TitleScreen {
@@ -860,11 +1232,17 @@ impl TitleScreen {
             buttons: vec![
                 new_game_button,
                 statistics_button,
-                // settings_button,
+                settings_button,
             ],
             mini_buttons: vec![
                 leader_board_button,
             ],
+            text_buttons: vec![
+                TextButton::new(100.0, 520.0, 320.0, "Watch replay".to_owned(), "replays".to_owned()),
+                TextButton::new(100.0, 590.0, 320.0, "Level editor".to_owned(), "editor".to_owned()),
+                TextButton::new(100.0, 660.0, 320.0, "Community levels".to_owned(), "community".to_owned()),
+                TextButton::new(100.0, 730.0, 320.0, "Practice".to_owned(), "practice".to_owned()),
+            ],
         }
     }
 
@@ -879,6 +1257,11 @@ impl TitleScreen {
                 return button.id.clone();
             }
         }
+        for button in &mut self.text_buttons {
+            if button.update() {
+                return button.id.clone();
+            }
+        }
         "continue".to_owned()
     }
 
@@ -890,7 +1273,10 @@ impl TitleScreen {
         for button in &self.mini_buttons {
             button.draw();
         }
-        draw_texture_ex(&player_texture, 1000.0 - (get_time().sin() * 16.0) as f32, 500.0 - (get_time().sin() * 16.0) as f32, WHITE, DrawTextureParams {
+        for button in &self.text_buttons {
+            button.draw(false);
+        }
+        draw_texture_ex(player_texture, 1000.0 - (get_time().sin() * 16.0) as f32, 500.0 - (get_time().sin() * 16.0) as f32, WHITE, DrawTextureParams {
             dest_size: Some(vec2((192.0 + get_time().sin() * 32.0) as f32, (144.0 + get_time().sin() * 32.0) as f32)),
             rotation: ((get_time() * 2.0).sin() * 0.5) as f32,
             ..Default::default()
@@ -898,187 +1284,1733 @@ impl TitleScreen {
     }
 }
 
+fn username_file(directory: &str) -> String {
+    format!("{}/username.txt", directory)
+}
+
+// Asks for a name until it's one the server would take, and saves it to username.txt
+async fn ask_username(directory: &str, message: &str) -> String {
+    let mut textbox = TextBox::new("Enter your username".to_owned());
+    let mut problem = message.to_owned();
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        textbox.update();
+        textbox.draw(100.0, 100.0);
+        draw_text(&problem, 100.0, 200.0, 30.0, RED);
+
+        if is_key_pressed(KeyCode::Enter) {
+            let name = textbox.text.trim().to_owned();
+            match accounts::check_name(&name) {
+                Ok(()) => {
+                    // Firstly make sure the directory itself is there
+                    create_dir_all(directory).unwrap();
+
+                    let mut file = File::create(username_file(directory)).unwrap();
+                    file.write_all(name.as_bytes()).unwrap();
+                    return name;
+                }
+                Err(e) => problem = e.to_owned(),
+            }
+        }
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+// Where the username, settings, replays and such are kept
+fn data_directory() -> String {
+    data_dir::data_directory().to_string_lossy().into_owned()
+}
+
 fn window_conf() -> Conf {
+    let settings = Settings::load(&data_directory()).with_overrides();
     Conf {
         window_title: "Hardest Game Ever".to_owned(),
-        window_width: WINDOW_WIDTH as i32,
-        window_height: WINDOW_HEIGHT as i32,
+        window_width: settings.window_width as i32,
+        window_height: settings.window_height as i32,
+        fullscreen: settings.fullscreen,
         ..Default::default()
     }
 }
 
 #[macroquad::main(window_conf)]
 async fn main() {
-    // Check if 127.0.0.1:3000 is reachable, if not, panic
-    if cfg!(debug_assertions) {
-        let response = get("http://127.0.0.1:3000/leaderboard").send().expect("Failed to connect to the server.\nPlease make sure the server is running on http://127.0.0.1:3000 for development.");
-        if response.status_code != 200 {
-            panic!("Failed to connect to the server.\nPlease make sure the server is running on http://127.0.0.1:3000 for development.");
+    let directory = data_directory();
+    // The settings screen changes what's in the file, the game runs with the overrides on top
+    let mut saved_settings = Settings::load(&directory);
+    let mut settings = saved_settings.clone().with_overrides();
+    let mut keys = Keys::new(&settings.keys);
+
+    let network = Network::new();
+
+    // ALL THE TEXTURES. Whatever is on disk is used right away, newer ones replace them once
+    // they're downloaded
+    let mut images: HashMap<String, Vec<u8>> = ASSET_NAMES.iter()
+        .map(|name| (name.to_string(), local_asset(&directory, name)))
+        .collect();
+    let mut asset_refresh = Some({
+        let (server_url, asset_url, directory) = (settings.server_url.clone(), settings.asset_url.clone(), directory.clone());
+        network.run_in_background(move || refresh_assets(&server_url, &asset_url, &directory))
+    });
+    let (mut textures, mut title_screen) = load_textures(&images);
+
+    // Without the server there's only the built in level
+    let mut levels: Vec<LevelInfo> = vec![LevelInfo {
+        id: DEFAULT_LEVEL.to_owned(),
+        name: "Classic".to_owned(),
+        difficulty: 1,
+    }];
+    let mut levels_request = Some({
+        let server_url = settings.server_url.clone();
+        // Not tried again either, it's quicker to find out the game is offline that way
+        network.run_in_background(move || get_json::<Vec<LevelInfo>>(&server_url, "/levels", 0))
+    });
+
+    // Ask for a username if there isn't one, or if it's one the server wouldn't take
+    let mut username = match read_to_string(username_file(&directory)) {
+        Ok(name) if accounts::check_name(name.trim()).is_ok() => name.trim().to_owned(),
+        _ => ask_username(&directory, "").await,
+    };
+
+    // Send whatever didn't make it to the server last time
+    let mut submitting = vec![send_scores(&network, &settings.server_url, &username, &directory, Vec::new())];
+
+    // The last leaderboards seen of every level, shown while a fresh one loads
+    let mut leaderboards: HashMap<(String, Metric), Vec<router::RankedScore>> = HashMap::new();
+    // What happened on every level that was played since the game started
+    let mut sessions: HashMap<String, Session> = HashMap::new();
+    // Community levels played since the game started, for their names
+    let mut community_played: Vec<LevelInfo> = Vec::new();
+    // The level whose leaderboard and statistics are shown
+    let mut current_level = DEFAULT_LEVEL.to_owned();
+
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        let next_screen = title_screen.update();
+        title_screen.draw(&textures.player);
+
+        // Keep an eye on whatever the network thread is doing for us
+        if let Some(pending) = &asset_refresh {
+            match pending.poll() {
+                Loading::Waiting => {}
+                Loading::Done(fresh) => {
+                    if !fresh.is_empty() {
+                        images.extend(fresh);
+                        (textures, title_screen) = load_textures(&images);
+                    }
+                    asset_refresh = None;
+                }
+                Loading::Failed(e) => {
+                    toast(format!("Couldn't check for new images: {}", e));
+                    asset_refresh = None;
+                }
+            }
+        }
+        if let Some(pending) = &levels_request {
+            match pending.poll() {
+                Loading::Waiting => {}
+                Loading::Done(fresh) => {
+                    levels = fresh;
+                    levels_request = None;
+                }
+                Loading::Failed(e) => {
+                    toast(format!("Playing offline, only the classic level is there: {}", e));
+                    levels_request = None;
+                }
+            }
+        }
+        // Someone else had the name, registering threw it away
+        if !Path::new(&username_file(&directory)).exists() {
+            username = ask_username(&directory, &format!("Someone already has the name {}, pick another one", username)).await;
+            submitting.push(send_scores(&network, &settings.server_url, &username, &directory, Vec::new()));
+        }
+        submitting.retain(|pending| match pending.poll() {
+            Loading::Waiting => true,
+            Loading::Done(report) => {
+                if report.sent > 0 {
+                    toast(format!("Submitted {} score(s)", report.sent));
+                }
+                if report.queued > 0 {
+                    toast(format!("{} score(s) will be sent once the server can be reached", report.queued));
+                }
+                false
+            }
+            Loading::Failed(e) => {
+                toast(e);
+                false
+            }
+        });
+
+        if is_key_pressed(keys.leave) {
+            // Every run is in the outbox already, just let whatever is still being sent finish
+            if !submitting.is_empty() {
+                wait_for(send_scores(&network, &settings.server_url, &username, &directory, Vec::new()), "Sending your scores", &keys).await;
+            }
+            break;
         }
-        drop(response);
+
+        let level_name = levels.iter()
+            .chain(&community_played)
+            .find(|l| l.id == current_level)
+            .map(|l| l.name.clone())
+            .unwrap_or(current_level.clone());
+
+        if next_screen == "new_game" || next_screen == "community" {
+            let picked = if next_screen == "new_game" {
+                level_select(&levels, &keys).await
+            } else {
+                community_levels(&network, &settings.server_url, &username, &keys).await
+            };
+            if let Some(level) = picked {
+                if let Some(world) = load_world(&network, &settings.server_url, &level.id, &level.name, &keys).await {
+                    let mut submit = |replay: &Replay| {
+                        let submission = run_submission(&username, replay);
+                        submitting.push(send_scores(&network, &settings.server_url, &username, &directory, vec![submission]));
+                    };
+                    let session = game(&level.id, &level.name, &world, &directory, &textures, &keys, &mut submit).await;
+                    match sessions.get_mut(&level.id) {
+                        Some(existing) => existing.merge(session),
+                        None => {
+                            sessions.insert(level.id.clone(), session);
+                        }
+                    }
+                    current_level = level.id.clone();
+                    if !levels.iter().chain(&community_played).any(|l| l.id == level.id) {
+                        community_played.push(level);
+                    }
+                }
+            }
+        } else if next_screen == "statistics" {
+            let stats = network.get_json(&settings.server_url, format!("/stats/{}?level={}", url_encode(&username), url_encode(&current_level)));
+            statistics(&level_name, stats, sessions.get(&current_level), &keys).await;
+        } else if next_screen == "leader_board" {
+            leaderboard(&network, &settings.server_url, &mut leaderboards, &current_level, &level_name, &username, &keys).await;
+        } else if next_screen == "settings" {
+            saved_settings = settings_screen(&directory, saved_settings, &keys).await;
+            let fullscreen = settings.fullscreen;
+            settings = saved_settings.clone().with_overrides();
+            keys = Keys::new(&settings.keys);
+            if settings.fullscreen != fullscreen {
+                set_fullscreen(settings.fullscreen);
+            }
+        } else if next_screen == "replays" {
+            if let Some(replay) = replay_select(&directory, &levels, &keys).await {
+                let name = levels.iter().find(|l| l.id == replay.level).map(|l| l.name.clone()).unwrap_or(replay.level.clone());
+                if let Some(world) = load_world(&network, &settings.server_url, &replay.level, &name, &keys).await {
+                    watch_replay(&replay, &name, &world, &textures, &keys).await;
+                }
+            }
+        } else if next_screen == "practice" {
+            // Nothing is recorded or sent, so it doesn't change the current level either
+            if let Some(level) = level_select(&levels, &keys).await {
+                if let Some(world) = load_world(&network, &settings.server_url, &level.id, &level.name, &keys).await {
+                    practice(&level.name, &world, world::SPAWN, &textures, &keys).await;
+                }
+            }
+        } else if next_screen == "editor" {
+            if let Some(level) = editor_select(&directory, &keys).await {
+                editor(&directory, level, &textures, &keys, &network, &settings.server_url, &username).await;
+            }
+        }
+
+        draw_toasts();
+        next_frame().await;
     }
+}
 
-    // ALL THE TEXTURES
-    let player_texture = get_image("player.png");
-    let wall_texture = get_image("wall.png");
-    let movingplatform_texture = get_image("movingplatform.png");
-    let speedportal_texture = get_image("speedportal.png");
-    let buttons_texture = get_image("buttons.png");
-    let minibuttons_texture = get_image("minibuttons.png");
+async fn level_select(levels: &[LevelInfo], keys: &Keys) -> Option<LevelInfo> {
+    let mut buttons: Vec<TextButton> = levels.iter().enumerate().map(|(i, level)| {
+        TextButton::new(100.0, 200.0 + i as f32 * 64.0, 600.0, format!("{} ({})", level.name, "*".repeat(level.difficulty as usize)), level.id.clone())
+    }).collect();
+    let mut selected = 0;
 
-    // let leaderboard_res = get("https://hardest-game-ever-d2ht.shuttle.app/leaderboard")
-    let leaderboard_res = get(format!("{}/leaderboard", SERVER_URL))
-        .send()
-        .unwrap()
-        .as_str()
-        .unwrap()
-        .to_string();
+    // Don't let the click on the "new game" button also pick a level
+    next_frame().await;
 
-    // let world_res = get("https://hardest-game-ever-d2ht.shuttle.app/world")
-    let world_res = get(format!("{}/world", SERVER_URL))
-        .send()
-        .unwrap()
-        .as_str()
-        .unwrap()
-        .to_string();
-
-    let host_username = env::var("USERNAME").unwrap();
-    #[cfg(target_os = "windows")]
-    let directory = format!("C:/Users/{}/AppData/Roaming/HardestGameEver", host_username);
-    #[cfg(target_os = "linux")]
-    let directory = format!("/home/{}/.local/share/HardestGameEver", host_username);
-
-    let username_file = format!("{}/username.txt", directory);
-
-    // Check if the username file exists, if not, make a screen to ask for the username and save it
-    if !Path::new(&username_file).exists() {
-        let mut textbox = TextBox::new("Enter your username".to_owned());
-        loop {
-            set_default_camera();
-            clear_background(BLACK);
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
 
-            textbox.update();
-            textbox.draw(100.0, 100.0);
+        if is_key_pressed(keys.leave) {
+            return None;
+        }
 
-            if is_key_pressed(KeyCode::Enter) {
-                // Firstly make sure the directory itself is there
-                create_dir_all(&directory).unwrap();
+        if is_key_pressed(KeyCode::Down) && selected + 1 < buttons.len() {
+            selected += 1;
+        }
+        if is_key_pressed(KeyCode::Up) && selected > 0 {
+            selected -= 1;
+        }
+        if is_key_pressed(KeyCode::Enter) && !levels.is_empty() {
+            return Some(levels[selected].clone());
+        }
 
-                let mut file = File::create(&username_file).unwrap();
-                file.write_all(textbox.text.trim().as_bytes()).unwrap();
-                break;
+        draw_text("Pick a level", 100.0, 100.0, 48.0, WHITE);
+        for (i, button) in buttons.iter_mut().enumerate() {
+            if button.update() {
+                return Some(levels[i].clone());
             }
+            button.draw(i == selected);
+        }
+        if levels.is_empty() {
+            draw_text("The server doesn't have any levels :(", 100.0, 200.0, 36.0, GRAY);
+        }
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
 
-            next_frame().await;
+async fn level_error(level_name: &str, error: &LoadError, keys: &Keys) {
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        if is_key_pressed(keys.leave) {
+            break;
+        }
+
+        match error {
+            LoadError::Unavailable(reason) => {
+                draw_text(&format!("Couldn't download {} :(", level_name), 100.0, 100.0, 48.0, RED);
+                draw_text(reason, 100.0, 180.0, 30.0, WHITE);
+                draw_text("Only the classic level works offline", 100.0, 230.0, 30.0, WHITE);
+            }
+            LoadError::Broken(error) => {
+                draw_text(&format!("{} is broken :(", level_name), 100.0, 100.0, 48.0, RED);
+                for (i, problem) in error.problems.iter().enumerate() {
+                    draw_text(&problem.to_string(), 100.0, 180.0 + i as f32 * 40.0, 30.0, WHITE);
+                }
+            }
         }
+        draw_text(&format!("Press {} to go back", key_name(keys.leave)), 100.0, 800.0, 24.0, GRAY);
+
+        draw_toasts();
+        next_frame().await;
     }
-    
-    let username = read_to_string(&username_file).unwrap();
-    
-    let mut title_screen = TitleScreen::new(&buttons_texture, &minibuttons_texture);
+}
 
-    let mut best_score = 0;
+async fn replay_select(directory: &str, levels: &[LevelInfo], keys: &Keys) -> Option<Replay> {
+    let replays = load_replays(directory);
+    let now = now_millis();
+    let mut buttons: Vec<TextButton> = replays.iter().enumerate().map(|(i, (time, replay))| {
+        let name = levels.iter().find(|l| l.id == replay.level).map(|l| l.name.as_str()).unwrap_or(&replay.level);
+        let minutes_ago = now.saturating_sub(*time) / 60_000;
+        let ago = if minutes_ago < 60 {
+            format!("{} min ago", minutes_ago)
+        } else if minutes_ago < 60 * 24 {
+            format!("{} h ago", minutes_ago / 60)
+        } else {
+            format!("{} days ago", minutes_ago / 60 / 24)
+        };
+        TextButton::new(100.0, 0.0, 900.0, format!("{} - {}s - {}", name, round(ticks_to_seconds(replay.ticks), 2), ago), i.to_string())
+    }).collect();
+    let mut selected = 0;
+
+    // Don't let the click on the title screen also pick a replay
+    next_frame().await;
 
     loop {
         set_default_camera();
         clear_background(BLACK);
 
-        let next_screen = title_screen.update();
-        title_screen.draw(&player_texture);
+        if is_key_pressed(keys.leave) {
+            return None;
+        }
+
+        if is_key_pressed(KeyCode::Down) && selected + 1 < buttons.len() {
+            selected += 1;
+        }
+        if is_key_pressed(KeyCode::Up) && selected > 0 {
+            selected -= 1;
+        }
+        if is_key_pressed(KeyCode::Enter) && !replays.is_empty() {
+            return Some(replays[selected].1.clone());
+        }
+
+        draw_text("Pick a replay", 100.0, 100.0, 48.0, WHITE);
+        // Only 10 fit on the screen, so scroll along with the selection
+        let first = selected.saturating_sub(9);
+        for (i, button) in buttons.iter_mut().enumerate().skip(first).take(10) {
+            button.hitbox.y = 160.0 + (i - first) as f32 * 64.0;
+            if button.update() {
+                return Some(replays[i].1.clone());
+            }
+            button.draw(i == selected);
+        }
+        if replays.is_empty() {
+            draw_text("No replays yet, go play a level first", 100.0, 200.0, 36.0, GRAY);
+        }
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+async fn watch_replay(replay: &Replay, level_name: &str, level: &world::World, textures: &Textures, keys: &Keys) {
+    let mut sim = Simulation::new(level);
+    let mut previous = sim.clone();
+    let mut timestep = FixedTimestep::new();
+    let mut finished = false;
+
+    let mut cam = Camera2D {
+        zoom: vec2(1.0 / WINDOW_WIDTH * 2.0, 1.0 / WINDOW_HEIGHT * 2.0),
+        ..Default::default()
+    };
+
+    loop {
+        set_camera(&cam);
+        clear_background(BLACK);
 
-        if is_key_pressed(KeyCode::Escape) {
-            submit_score(username.as_str(), best_score);
+        if is_key_pressed(keys.leave) {
             break;
         }
 
-        if is_quit_requested() {
-            // SAVE THE SCORE BEFORE IT'S TOO LATE
-            submit_score(username.as_str(), best_score);
+        // Space watches it again once it's over
+        if finished && is_key_pressed(keys.jump) {
+            sim.restart();
+            previous = sim.clone();
+            timestep.reset();
+            finished = false;
         }
 
-        if next_screen == "new_game" {
-            best_score = game(world_res.clone(), player_texture.clone(), wall_texture.clone(), movingplatform_texture.clone(), speedportal_texture.clone()).await;
-            // // Submit the score to the server on a separate thread
-            // std::thread::spawn(move || {
-            //     submit_score(username, best_score);
-            // });
-        } else if next_screen == "statistics" {
-            // statistics().await;
-            // Show best score if its higher than leaderboard score
-            let mut leaderboard_selfbest = 0;
-            let selfname = env::var("USERNAME").unwrap_or("Player".to_owned());
-            for score in serde_json::from_str::<Vec<router::Score>>(&leaderboard_res.as_str()).unwrap() {
-                if score.player == selfname {
-                    leaderboard_selfbest = score.score;
-                    break;
+        for _ in 0..timestep.advance(get_frame_time()) {
+            if sim.tick >= replay.ticks {
+                finished = true;
+                break;
+            }
+            previous = sim.clone();
+            sim.step(replay.input(sim.tick));
+        }
+
+        let (player, world) = if finished {
+            (sim.player.clone(), sim.world.clone())
+        } else {
+            interpolate(&previous, &sim, timestep.alpha())
+        };
+
+        cam.target.x = camera_x(&player);
+
+        draw_player(&player, &textures.player);
+        draw_world(&world, textures);
+
+        set_default_camera();
+
+        draw_text(&format!("Replay - {}", level_name), 10.0, 50.0, 30.0, WHITE);
+        draw_text(&format!("Time: {}", round(ticks_to_seconds(sim.tick), 2)), 10.0, 100.0, 30.0, WHITE);
+        if replay.version != REPLAY_VERSION {
+            draw_text("This replay was recorded with an older version of the game, it might not play back right", 10.0, 150.0, 24.0, ORANGE);
+        }
+        if finished {
+            draw_text(&format!("End of the replay, {} to watch again, {} to go back", key_name(keys.jump), key_name(keys.leave)), 10.0, 850.0, 30.0, GOLD);
+        }
+
+        draw_toasts();
+        next_frame().await
+    }
+}
+
+// Edits the settings file, returns what was saved
+async fn settings_screen(directory: &str, mut settings: Settings, keys: &Keys) -> Settings {
+    let labels = ["Server", "Assets", "Window width", "Window height", "Fullscreen", "Volume", "Jump key", "Leave key"];
+    let mut buttons: Vec<TextButton> = labels.iter().enumerate().map(|(i, label)| {
+        TextButton::new(100.0, 160.0 + i as f32 * 64.0, 1200.0, label.to_string(), i.to_string())
+    }).collect();
+    let mut selected = 0;
+    // The text being typed in for the server or the assets
+    let mut editing: Option<TextBox> = None;
+    let mut waiting_for_key = false;
+
+    // Don't let the click on the title screen also press something
+    next_frame().await;
+
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        let mut activate = false;
+        if let Some(textbox) = &mut editing {
+            textbox.update();
+            if is_key_pressed(KeyCode::Enter) {
+                let text = textbox.text.trim().trim_end_matches('/').to_owned();
+                if selected == 0 {
+                    settings.server_url = text;
+                } else {
+                    settings.asset_url = text;
+                }
+                editing = None;
+            } else if is_key_pressed(keys.leave) {
+                editing = None;
+            }
+        } else if waiting_for_key {
+            if let Some(key) = get_last_key_pressed() {
+                // Only keys with a name can be saved, anything else is ignored
+                if key_code(key_name(key)).is_some() {
+                    if selected == 6 {
+                        settings.keys.jump = key_name(key).to_owned();
+                    } else {
+                        settings.keys.leave = key_name(key).to_owned();
+                    }
+                    waiting_for_key = false;
                 }
             }
-            let score = if best_score > leaderboard_selfbest {
-                best_score
+        } else {
+            if is_key_pressed(keys.leave) {
+                break;
+            }
+            if is_key_pressed(KeyCode::Down) && selected + 1 < buttons.len() {
+                selected += 1;
+            }
+            if is_key_pressed(KeyCode::Up) && selected > 0 {
+                selected -= 1;
+            }
+            activate = is_key_pressed(KeyCode::Enter);
+
+            let step = if is_key_pressed(KeyCode::Right) {
+                1
+            } else if is_key_pressed(KeyCode::Left) {
+                -1
             } else {
-                leaderboard_selfbest
+                0
             };
-            statistics(score).await;
-        } else if next_screen == "leader_board" {
-            leaderboard(leaderboard_res.clone()).await;
+            match selected {
+                2 => settings.window_width = (settings.window_width as i32 + step * 100).max(400) as u32,
+                3 => settings.window_height = (settings.window_height as i32 + step * 100).max(300) as u32,
+                5 => settings.volume = round((settings.volume + step as f32 * 0.1).clamp(0.0, 1.0), 1),
+                _ => {}
+            }
         }
 
+        let values = [
+            settings.server_url.clone(),
+            settings.asset_url.clone(),
+            settings.window_width.to_string(),
+            settings.window_height.to_string(),
+            if settings.fullscreen { "On".to_owned() } else { "Off".to_owned() },
+            // Kept for when there are sounds
+            format!("{}% (the game has no sounds yet)", (settings.volume * 100.0).round()),
+            settings.keys.jump.clone(),
+            settings.keys.leave.clone(),
+        ];
+
+        draw_text("Settings", 100.0, 100.0, 48.0, WHITE);
+        for (i, button) in buttons.iter_mut().enumerate() {
+            if editing.is_none() && !waiting_for_key && button.update() {
+                selected = i;
+                activate = true;
+            }
+            button.text = if i == selected && waiting_for_key {
+                format!("{}: press a key", labels[i])
+            } else {
+                format!("{}: {}", labels[i], values[i])
+            };
+            button.draw(i == selected);
+        }
+        if let Some(textbox) = &editing {
+            draw_rectangle(100.0, 160.0 + selected as f32 * 64.0, 1200.0, 48.0, BLACK);
+            textbox.draw(100.0, 160.0 + selected as f32 * 64.0 + 34.0);
+        }
+
+        if activate {
+            match selected {
+                0 | 1 => {
+                    let mut textbox = TextBox::new(labels[selected].to_owned());
+                    textbox.text = values[selected].clone();
+                    textbox.cursor_pos = textbox.text.len() as u32;
+                    editing = Some(textbox);
+                }
+                4 => settings.fullscreen = !settings.fullscreen,
+                6 | 7 => waiting_for_key = true,
+                _ => {}
+            }
+        }
+
+        let help = format!("Up and down to pick, enter to change, left and right for numbers, {} to save", key_name(keys.leave));
+        draw_text(&help, 100.0, 760.0, 24.0, GRAY);
+        draw_text("The server, assets and window size are used after restarting the game", 100.0, 800.0, 24.0, GRAY);
+
+        draw_toasts();
         next_frame().await;
     }
+
+    if let Err(e) = settings.save(directory) {
+        eprintln!("Couldn't save the settings ({})", e);
+    }
+    settings
 }
 
-async fn statistics(leaderboard_selfbest: u32) {
+fn format_duration(ticks: u64) -> String {
+    let seconds = ticks / TICK_RATE as u64;
+    if seconds >= 3600 {
+        format!("{}h {}m", seconds / 3600, seconds / 60 % 60)
+    } else if seconds >= 60 {
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// Every run's score (dots) and the best so far (line), oldest on the left
+fn draw_progress(progress: &[Progress], x: f32, y: f32, width: f32, height: f32) {
+    draw_rectangle_lines(x, y, width, height, 2.0, GRAY);
+    let Some(best) = progress.last().map(|p| p.best).filter(|best| *best > 0) else {
+        return;
+    };
+    let point = |i: usize, score: u32| {
+        let along = if progress.len() > 1 { i as f32 / (progress.len() - 1) as f32 } else { 0.5 };
+        vec2(x + 20.0 + along * (width - 40.0), y + height - 20.0 - score as f32 / best as f32 * (height - 40.0))
+    };
+    for (i, pair) in progress.windows(2).enumerate() {
+        let (a, b) = (point(i, pair[0].best), point(i + 1, pair[1].best));
+        draw_line(a.x, a.y, b.x, b.y, 3.0, GOLD);
+    }
+    for (i, run) in progress.iter().enumerate() {
+        let p = point(i, run.score);
+        draw_circle(p.x, p.y, 5.0, WHITE);
+    }
+    draw_text(&format!("{}s", round(ticks_to_seconds(best), 2)), x + 8.0, y + 20.0, 20.0, GOLD);
+}
+
+async fn statistics(level_name: &str, request: Pending<PlayerStats>, session: Option<&Session>, keys: &Keys) {
+    let session_best = session.map(|s| s.best.ticks).unwrap_or(0);
+    let mut loading = true;
+    let mut stats = None;
+
     loop {
         set_default_camera();
         clear_background(BLACK);
 
-        if is_key_pressed(KeyCode::Escape) {
+        if is_key_pressed(keys.leave) {
             break;
         }
 
-        draw_text("Statistics", 100.0, 100.0, 48.0, WHITE);
-        draw_text(&format!("Your best score: {}s", round(leaderboard_selfbest as f32 / 60.0, 2)), 100.0, 200.0, 36.0, GOLD);
+        if loading {
+            match request.poll() {
+                Loading::Waiting => {}
+                Loading::Done(result) => {
+                    stats = Some(result);
+                    loading = false;
+                }
+                // Not having played the level is "failing" too, so the screen explains instead of a toast
+                Loading::Failed(_) => loading = false,
+            }
+        }
+        let stats = stats.as_ref();
+        let best = stats.map(|s| s.best).unwrap_or(0).max(session_best);
+
+        draw_text(&format!("Statistics - {}", level_name), 100.0, 100.0, 48.0, WHITE);
+        draw_text(&format!("Your best score: {}s", round(ticks_to_seconds(best), 2)), 100.0, 170.0, 36.0, GOLD);
+
+        match stats {
+            Some(stats) => {
+                let lines = [
+                    format!("Runs submitted: {}", stats.runs),
+                    format!("Average score: {}s", round(stats.average / TICK_RATE as f32, 2)),
+                    format!("Time played: {}", format_duration(stats.total_playtime)),
+                    format!("Attempts: {}", stats.total_attempts),
+                    format!("Improvement since your first run: {}s", round(ticks_to_seconds(stats.improvement), 2)),
+                    match stats.best_completion {
+                        Some(fastest) => format!("Finished {} times, the fastest in {}s", stats.completions, round(ticks_to_seconds(fastest), 2)),
+                        None => "Never finished".to_owned(),
+                    },
+                ];
+                for (i, line) in lines.iter().enumerate() {
+                    draw_text(line, 100.0, 230.0 + i as f32 * 40.0, 30.0, WHITE);
+                }
+                draw_progress(&stats.progress, 100.0, 460.0, 1200.0, 260.0);
+            }
+            None if loading => {
+                draw_text("Loading...", 100.0, 230.0, 30.0, GRAY);
+            }
+            None => {
+                draw_text("No stats from the server, either it can't be reached or you haven't submitted anything here", 100.0, 230.0, 24.0, GRAY);
+            }
+        }
+
+        if let Some(session) = session {
+            draw_text(
+                &format!("This session: {} attempts, {} played, best {}s", session.attempts, format_duration(session.playtime as u64), round(ticks_to_seconds(session_best), 2)),
+                100.0, 780.0, 24.0, SKYBLUE,
+            );
+        }
 
+        draw_toasts();
         next_frame().await;
     }
 }
 
-async fn leaderboard(response: String) {
-    let scores: Vec<router::Score>;
+// How many leaderboard entries fit on the screen at once
+const LEADERBOARD_ROWS: usize = 12;
+// How many scores are fetched at once, the next ones are fetched when scrolling gets near them
+const LEADERBOARD_PAGE: usize = 50;
+
+// Where the player is on a leaderboard and the players around them, as many as fit on the screen.
+// None if they aren't on it
+fn get_player_rank(server_url: &str, level: &str, metric: Metric, username: &str) -> Result<Option<router::PlayerRank>, String> {
+    let response = send(get(format!(
+        "{}/leaderboard/player/{}?level={}&metric={}&around={}",
+        server_url, url_encode(username), url_encode(level), metric.name(), (LEADERBOARD_ROWS - 1) / 2,
+    )))?;
+    let body = response.as_str().map_err(|e| e.to_string())?;
+    match response.status_code {
+        200 => serde_json::from_str(body).map(Some).map_err(|e| format!("The server sent something weird ({})", e)),
+        404 => Ok(None),
+        status => Err(format!("{} ({})", body, status)),
+    }
+}
 
-    scores = serde_json::from_str(&response.as_str()).unwrap();
+// `scores` is whatever was loaded last time, it's replaced by the first page once `refresh` is done
+// Left and right switch between the survival times and the times it took to finish
+async fn leaderboard(
+    network: &Network,
+    server_url: &str,
+    leaderboards: &mut HashMap<(String, Metric), Vec<router::RankedScore>>,
+    level: &str,
+    level_name: &str,
+    username: &str,
+    keys: &Keys,
+) {
+    let fetch = |metric: Metric, offset: usize| -> Pending<Vec<router::RankedScore>> {
+        network.get_json(server_url, format!(
+            "/leaderboard?level={}&metric={}&offset={}&limit={}",
+            url_encode(level), metric.name(), offset, LEADERBOARD_PAGE,
+        ))
+    };
+    let fetch_rank = |metric: Metric| {
+        let (server_url, level, username) = (server_url.to_owned(), level.to_owned(), username.to_owned());
+        network.run(move || get_player_rank(&server_url, &level, metric, &username))
+    };
+    let mut metric = Metric::Survival;
+    let mut refresh = fetch(metric, 0);
+    // The page after what's loaded, asked for when scrolling gets close to it
+    let mut next_page: Option<Pending<Vec<router::RankedScore>>> = None;
+    let mut more = false;
+    let mut rank_request = Some(fetch_rank(metric));
+    let mut me: Option<router::PlayerRank> = None;
+    // Showing the players around you instead of the top
+    let mut around_me = false;
+    let mut loading = true;
+    let mut failed = false;
+    let mut scroll = 0;
 
     loop {
         set_default_camera();
         clear_background(BLACK);
 
-        if is_key_pressed(KeyCode::Escape) {
+        if is_key_pressed(keys.leave) {
             break;
         }
 
-        for i in 0..scores.len() {
-            let color = if i == 0 {
-                GOLD
-            } else if i == 1 {
-                GRAY
-            } else if i == 2 {
-                BROWN
-            } else {
-                WHITE
+        if is_key_pressed(KeyCode::Left) || is_key_pressed(KeyCode::Right) {
+            metric = match metric {
+                Metric::Survival => Metric::Completion,
+                Metric::Completion => Metric::Survival,
             };
-            draw_text(&format!("{}: {} - {}s", i + 1, scores[i].player, round(scores[i].score as f32 / 60.0, 2)), 100.0, 100.0 + i as f32 * 50.0, 36.0 + (10.0 - i as f32) * 4.0, color);
+            refresh = fetch(metric, 0);
+            next_page = None;
+            rank_request = Some(fetch_rank(metric));
+            me = None;
+            around_me = false;
+            loading = true;
+            failed = false;
+            scroll = 0;
+        }
+        let scores = leaderboards.entry((level.to_owned(), metric)).or_default();
+
+        if loading {
+            match refresh.poll() {
+                Loading::Waiting => {}
+                Loading::Done(fresh) => {
+                    more = fresh.len() == LEADERBOARD_PAGE;
+                    *scores = fresh;
+                    loading = false;
+                }
+                Loading::Failed(e) => {
+                    toast(format!("Couldn't refresh the leaderboard: {}", e));
+                    failed = true;
+                    loading = false;
+                }
+            }
+        }
+        if let Some(pending) = &next_page {
+            match pending.poll() {
+                Loading::Waiting => {}
+                Loading::Done(fresh) => {
+                    more = fresh.len() == LEADERBOARD_PAGE;
+                    scores.extend(fresh);
+                    next_page = None;
+                }
+                Loading::Failed(e) => {
+                    toast(format!("Couldn't load more of the leaderboard: {}", e));
+                    more = false;
+                    next_page = None;
+                }
+            }
+        }
+        if let Some(pending) = &rank_request {
+            match pending.poll() {
+                Loading::Waiting => {}
+                Loading::Done(rank) => {
+                    me = rank;
+                    rank_request = None;
+                }
+                Loading::Failed(e) => {
+                    eprintln!("Couldn't find you on the leaderboard: {}", e);
+                    rank_request = None;
+                }
+            }
         }
 
-        draw_text("Leaderboard may not be up to date, restart the game to refresh", 100.0, 800.0, 24.0, GRAY);
+        if let (true, Some(_)) = (is_key_pressed(KeyCode::Tab), &me) {
+            around_me = !around_me;
+        }
+        let max_scroll = scores.len().saturating_sub(LEADERBOARD_ROWS);
+        scroll = scroll.min(max_scroll);
+        if !around_me {
+            let wheel = mouse_wheel().1;
+            if (is_key_pressed(KeyCode::Down) || wheel < 0.0) && scroll < max_scroll {
+                scroll += 1;
+            }
+            if (is_key_pressed(KeyCode::Up) || wheel > 0.0) && scroll > 0 {
+                scroll -= 1;
+            }
+            if is_key_pressed(KeyCode::PageDown) {
+                scroll = (scroll + LEADERBOARD_ROWS).min(max_scroll);
+            }
+            if is_key_pressed(KeyCode::PageUp) {
+                scroll = scroll.saturating_sub(LEADERBOARD_ROWS);
+            }
+        }
+        if more && !loading && next_page.is_none() && scroll + LEADERBOARD_ROWS * 2 >= scores.len() {
+            next_page = Some(fetch(metric, scores.len()));
+        }
+
+        let rows = match (&me, around_me) {
+            (Some(me), true) => &me.entries[..],
+            _ => &scores[scroll..(scroll + LEADERBOARD_ROWS).min(scores.len())],
+        };
+        for (row, entry) in rows.iter().enumerate() {
+            let y = 150.0 + row as f32 * 50.0;
+            let color = match entry.rank {
+                1 => GOLD,
+                2 => GRAY,
+                3 => BROWN,
+                _ => WHITE,
+            };
+            // Names are case insensitive, so this is you whatever case the server has it in
+            if entry.score.player.eq_ignore_ascii_case(username) {
+                draw_rectangle(90.0, y - 36.0, 1000.0, 48.0, Color::new(0.125, 0.125, 0.25, 1.0));
+                draw_text(">", 60.0, y, 36.0, SKYBLUE);
+            }
+            draw_text(&format!("{}: {} - {}s", entry.rank, entry.score.player, round(ticks_to_seconds(entry.score.score), 2)), 100.0, y, 36.0, color);
+        }
+        if scores.is_empty() && !loading {
+            draw_text("Nobody has a score on this level yet", 100.0, 150.0, 36.0, GRAY);
+        }
+
+        let title = match metric {
+            Metric::Survival => "longest survived",
+            Metric::Completion => "fastest to the finish",
+        };
+        draw_text(&format!("Leaderboard - {} - {}", level_name, title), 100.0, 80.0, 48.0, WHITE);
+
+        let position = match (&me, around_me) {
+            (Some(me), false) => format!("You're #{}, tab to find yourself", me.rank),
+            (Some(me), true) => format!("You're #{}, tab to go back to the top", me.rank),
+            (None, _) => "You don't have a score on this level yet".to_owned(),
+        };
+        draw_text(&format!("{} (up/down to scroll, left/right for the other leaderboard)", position), 100.0, 760.0, 24.0, SKYBLUE);
+        if loading {
+            draw_text("Refreshing...", 100.0, 800.0, 24.0, GRAY);
+        } else if failed {
+            draw_text("Couldn't reach the server, this leaderboard may be out of date", 100.0, 800.0, 24.0, RED);
+        }
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+// Publishes a level made in the editor, for everyone to play
+fn upload_level(server_url: &str, directory: &str, username: &str, upload: &Upload) -> Result<CommunityLevelInfo, String> {
+    let account = get_account(server_url, directory, username)?;
+    let response = send(post(format!("{}/levels", server_url))
+        .with_header("Content-Type", "application/json")
+        .with_header("Authorization", format!("Bearer {}", account.token))
+        .with_body(serde_json::to_string(upload).unwrap()))?;
+    let body = response.as_str().map_err(|e| e.to_string())?;
+    if response.status_code != 200 {
+        return Err(body.to_owned());
+    }
+    serde_json::from_str(body).map_err(|e| format!("The server sent something weird ({})", e))
+}
+
+// The ways community levels can be listed: the newest, the most played, or only your own
+const LISTINGS: [&str; 3] = ["Newest", "Most played", "Yours"];
+
+// Levels other players made. Returns the one to play
+async fn community_levels(network: &Network, server_url: &str, username: &str, keys: &Keys) -> Option<LevelInfo> {
+    let mut tabs: Vec<TextButton> = LISTINGS.iter().enumerate().map(|(i, name)| {
+        TextButton::new(100.0 + i as f32 * 260.0, 130.0, 250.0, name.to_string(), i.to_string())
+    }).collect();
+    let mut search = TextBox::new("Search by title, enter to search".to_owned());
+    let browse = |listing: usize, search: &str| {
+        let query = match listing {
+            0 => "sort=newest".to_owned(),
+            1 => "sort=most_played".to_owned(),
+            _ => format!("author={}", url_encode(username)),
+        };
+        network.get_json(server_url, format!("/levels/community?{}&search={}", query, url_encode(search)))
+    };
+
+    let mut listing = 0;
+    let mut searched = String::new();
+    let mut request: Option<Pending<Vec<CommunityLevelInfo>>> = Some(browse(listing, &searched));
+    let mut found: Vec<CommunityLevelInfo> = Vec::new();
+    let mut buttons: Vec<TextButton> = Vec::new();
+    let mut failed = None;
+    let mut selected = 0;
+
+    // Don't let the click on the title screen also pick a level
+    next_frame().await;
+
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        if is_key_pressed(keys.leave) {
+            return None;
+        }
+
+        if let Some(pending) = &request {
+            match pending.poll() {
+                Loading::Waiting => {}
+                Loading::Done(levels) => {
+                    buttons = levels.iter().enumerate().map(|(i, level)| {
+                        let text = format!("{} by {} ({}) - {} plays", level.title, level.author, "*".repeat(level.difficulty as usize), level.plays);
+                        TextButton::new(100.0, 0.0, 1200.0, text, i.to_string())
+                    }).collect();
+                    found = levels;
+                    selected = 0;
+                    failed = None;
+                    request = None;
+                }
+                Loading::Failed(e) => {
+                    failed = Some(e);
+                    request = None;
+                }
+            }
+        }
+
+        search.update();
+        let mut switched = is_key_pressed(KeyCode::Tab);
+        if switched {
+            listing = (listing + 1) % LISTINGS.len();
+        }
+        for (i, tab) in tabs.iter_mut().enumerate() {
+            if tab.update() {
+                listing = i;
+                switched = true;
+            }
+        }
+        if is_key_pressed(KeyCode::Down) && selected + 1 < buttons.len() {
+            selected += 1;
+        }
+        if is_key_pressed(KeyCode::Up) && selected > 0 {
+            selected -= 1;
+        }
+        let typed = search.text.trim().to_owned();
+        if switched || (is_key_pressed(KeyCode::Enter) && typed != searched) {
+            searched = typed;
+            request = Some(browse(listing, &searched));
+        } else if is_key_pressed(KeyCode::Enter) && !found.is_empty() {
+            let level = &found[selected];
+            return Some(LevelInfo { id: level.id.clone(), name: level.title.clone(), difficulty: level.difficulty });
+        }
+
+        draw_text("Community levels", 100.0, 100.0, 48.0, WHITE);
+        for (i, tab) in tabs.iter().enumerate() {
+            tab.draw(i == listing);
+        }
+        search.draw(100.0, 240.0);
+
+        // Only 9 fit on the screen, so scroll along with the selection
+        let first = selected.saturating_sub(8);
+        for (i, button) in buttons.iter_mut().enumerate().skip(first).take(9) {
+            button.hitbox.y = 280.0 + (i - first) as f32 * 64.0;
+            if button.update() {
+                let level = &found[i];
+                return Some(LevelInfo { id: level.id.clone(), name: level.title.clone(), difficulty: level.difficulty });
+            }
+            button.draw(i == selected);
+        }
+        if request.is_some() {
+            draw_text("Loading...", 100.0, 320.0, 30.0, GRAY);
+        } else if let Some(e) = &failed {
+            draw_text(&format!("Couldn't get the levels: {}", e), 100.0, 320.0, 30.0, RED);
+        } else if found.is_empty() {
+            draw_text("Nothing here yet, make one in the level editor and press U to upload it", 100.0, 320.0, 30.0, GRAY);
+        }
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+// The level editor. Levels made with it are kept in <directory>/levels/<id>.json, in the same
+// format as the server's levels directory
+
+fn editor_levels_directory(directory: &str) -> String {
+    format!("{}/levels", directory)
+}
+
+// Every level made with the editor, by name. Ones that can't be read are left alone, the editor
+// can't show them
+fn load_editor_levels(directory: &str) -> Vec<EditorLevel> {
+    let Ok(entries) = fs::read_dir(editor_levels_directory(directory)) else {
+        return Vec::new();
+    };
+    let mut levels: Vec<EditorLevel> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let id = path.file_stem()?.to_str()?.to_owned();
+            match parse_editor_level(&id, &read_to_string(&path).ok()?) {
+                Ok((level, problems)) => {
+                    if let Some(error) = problems {
+                        toast(format!("{} needs fixing ({})", level.name, error));
+                    }
+                    Some(level)
+                }
+                Err(e) => {
+                    eprintln!("Skipping {} ({})", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    levels.sort_by(|a, b| a.name.cmp(&b.name));
+    levels
+}
+
+fn save_editor_level(directory: &str, level: &EditorLevel) -> std::io::Result<()> {
+    let levels_directory = editor_levels_directory(directory);
+    create_dir_all(&levels_directory)?;
+    let file = LevelFile {
+        name: level.name.clone(),
+        difficulty: level.difficulty,
+        world: serde_json::to_value(&level.world).unwrap(),
+    };
+    fs::write(format!("{}/{}.json", levels_directory, level.id), serde_json::to_string_pretty(&file).unwrap())
+}
+
+// What a new level starts out as, a floor and a ceiling like the classic level and a finish line
+// at the end of them
+fn empty_world() -> world::World {
+    world::World {
+        objects: vec![
+            world::Rect { x: 0.0, y: 250.0, width: 5000.0, height: 50.0 },
+            world::Rect { x: 0.0, y: -300.0, width: 5000.0, height: 50.0 },
+        ],
+        poly_objects: Vec::new(),
+        moving_objects: Vec::new(),
+        speed_increases: Vec::new(),
+        finish: Some(world::FinishLine { x: 4900.0 }),
+        checkpoints: Vec::new(),
+        portals: Vec::new(),
+    }
+}
+
+// Picks a level to edit, or makes a new one when a name is typed in
+async fn editor_select(directory: &str, keys: &Keys) -> Option<EditorLevel> {
+    let mut levels = load_editor_levels(directory);
+    let mut buttons: Vec<TextButton> = levels.iter().enumerate().map(|(i, level)| {
+        TextButton::new(100.0, 0.0, 900.0, level.name.clone(), i.to_string())
+    }).collect();
+    let mut name = TextBox::new("Name of a new level".to_owned());
+    let mut selected = 0;
+
+    // Don't let the click on the title screen also pick a level
+    next_frame().await;
+
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        if is_key_pressed(keys.leave) {
+            return None;
+        }
+
+        name.update();
+        if is_key_pressed(KeyCode::Down) && selected + 1 < buttons.len() {
+            selected += 1;
+        }
+        if is_key_pressed(KeyCode::Up) && selected > 0 {
+            selected -= 1;
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            let name = name.text.trim();
+            if !name.is_empty() {
+                return Some(EditorLevel {
+                    id: editor_level_id(name, &levels),
+                    name: name.to_owned(),
+                    difficulty: 1,
+                    world: empty_world(),
+                });
+            }
+            if !levels.is_empty() {
+                return Some(levels.remove(selected));
+            }
+        }
+
+        draw_text("Level editor", 100.0, 100.0, 48.0, WHITE);
+        name.draw(100.0, 180.0);
+        draw_text("Type a name and press enter to start a new level, or pick one to keep working on", 100.0, 230.0, 24.0, GRAY);
+        // Only 9 fit on the screen, so scroll along with the selection
+        let first = selected.saturating_sub(8);
+        for (i, button) in buttons.iter_mut().enumerate().skip(first).take(9) {
+            button.hitbox.y = 270.0 + (i - first) as f32 * 64.0;
+            if button.update() {
+                return Some(levels.remove(i));
+            }
+            button.draw(i == selected);
+        }
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tool {
+    Select,
+    Wall,
+    Polygon,
+    Mover,
+    Portal,
+    Finish,
+    Checkpoint,
+}
+
+const TOOLS: [(Tool, &str); 7] = [
+    (Tool::Select, "1 Select"),
+    (Tool::Wall, "2 Wall"),
+    (Tool::Polygon, "3 Polygon"),
+    (Tool::Mover, "4 Mover"),
+    (Tool::Portal, "5 Portal"),
+    (Tool::Finish, "6 Finish"),
+    (Tool::Checkpoint, "7 Checkpoint"),
+];
+
+// What the portal tool places, tab goes through them. `None` is a speed portal, the kind that
+// multiplies the speed
+const PORTAL_CHOICES: [(&str, Option<PortalKind>); 5] = [
+    ("Speed portal", None),
+    ("Set speed portal", Some(PortalKind::Speed { speed: 5.0 })),
+    ("Gravity portal", Some(PortalKind::Gravity { inverted: true })),
+    ("Size portal", Some(PortalKind::Size { size: PlayerSize::Mini })),
+    ("Mirror portal", Some(PortalKind::Mirror { mirrored: true })),
+];
+
+fn describe_portal(kind: PortalKind) -> String {
+    match kind {
+        PortalKind::Gravity { inverted: true } => "Gravity portal, turns it upside down".to_owned(),
+        PortalKind::Gravity { inverted: false } => "Gravity portal, back to normal".to_owned(),
+        PortalKind::Size { size } => format!("Size portal, {}", format!("{:?}", size).to_lowercase()),
+        PortalKind::Mirror { mirrored: true } => "Mirror portal, right to left".to_owned(),
+        PortalKind::Mirror { mirrored: false } => "Mirror portal, back to normal".to_owned(),
+        PortalKind::Speed { speed } => format!("Set speed portal, {}", speed),
+    }
+}
+
+// What +/- does to a portal: speed goes up or down, sizes go one bigger or smaller and the rest
+// flip
+fn change_portal(kind: PortalKind, change: f32) -> PortalKind {
+    match kind {
+        PortalKind::Gravity { inverted } => PortalKind::Gravity { inverted: !inverted },
+        PortalKind::Mirror { mirrored } => PortalKind::Mirror { mirrored: !mirrored },
+        PortalKind::Size { size } => {
+            let i = PlayerSize::ALL.iter().position(|s| *s == size).unwrap_or(1) as i32 + change as i32;
+            PortalKind::Size { size: PlayerSize::ALL[i.clamp(0, 2) as usize] }
+        }
+        PortalKind::Speed { speed } => PortalKind::Speed { speed: (speed + change * 0.5).max(0.5) },
+    }
+}
+
+fn describe(level: &world::World, object: Object) -> String {
+    match object {
+        Object::Wall(i) => {
+            let o = &level.objects[i];
+            format!("Wall, {}x{}", o.width, o.height)
+        }
+        Object::Polygon(i) => format!("Polygon, {} points (V adds one after the grabbed point)", level.poly_objects[i].points.len()),
+        Object::Mover(i) => {
+            let o = &level.moving_objects[i];
+            format!("Mover, {}x{}, speed {} (+/- to change)", o.width, o.height, o.speed)
+        }
+        Object::SpeedPortal(i) => format!("Speed portal, x{} (+/- to change)", level.speed_increases[i].speed_change),
+        Object::Portal(i) => format!("{} (+/- to change)", describe_portal(level.portals[i].kind)),
+        Object::Finish => "Finish line".to_owned(),
+        Object::Checkpoint(_) => "Checkpoint, only counts in practice mode".to_owned(),
+    }
+}
+
+fn draw_outline(points: &[Vec2], thickness: f32, color: Color) {
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        draw_line(a.x, a.y, b.x, b.y, thickness, color);
+    }
+}
+
+fn rect_points(x: f32, y: f32, width: f32, height: f32) -> [Vec2; 4] {
+    [vec2(x, y), vec2(x + width, y), vec2(x + width, y + height), vec2(x, y + height)]
+}
+
+fn object_outline(level: &world::World, object: Object) -> Vec<Vec2> {
+    match object {
+        Object::Wall(i) => {
+            let o = &level.objects[i];
+            rect_points(o.x, o.y, o.width, o.height).to_vec()
+        }
+        Object::Polygon(i) => level.poly_objects[i].points.iter().copied().map(to_vec).collect(),
+        Object::Mover(i) => {
+            let o = &level.moving_objects[i];
+            rect_points(o.from.x, o.from.y, o.width, o.height).to_vec()
+        }
+        Object::SpeedPortal(i) => {
+            let o = &level.speed_increases[i];
+            rect_points(o.x, o.y, world::PORTAL_WIDTH, world::PORTAL_HEIGHT).to_vec()
+        }
+        Object::Portal(i) => {
+            let o = &level.portals[i];
+            rect_points(o.x, o.y, world::PORTAL_WIDTH, world::PORTAL_HEIGHT).to_vec()
+        }
+        Object::Finish => {
+            let x = level.finish.map(|f| f.x).unwrap_or(0.0);
+            rect_points(x, -64.0 * FINISH_SQUARE, FINISH_SQUARE * 2.0, 128.0 * FINISH_SQUARE).to_vec()
+        }
+        Object::Checkpoint(i) => {
+            let x = level.checkpoints[i].x;
+            rect_points(x, -64.0 * CHECKPOINT_DASH, CHECKPOINT_WIDTH, 128.0 * CHECKPOINT_DASH).to_vec()
+        }
+    }
+}
+
+async fn editor(directory: &str, mut level: EditorLevel, textures: &Textures, keys: &Keys, network: &Network, server_url: &str, username: &str) {
+    let mut tool = Tool::Select;
+    // Which of PORTAL_CHOICES the portal tool places
+    let mut portal_choice = 0;
+    // As wide as their names, one after the other
+    let mut x = 10.0;
+    let mut tool_buttons: Vec<TextButton> = TOOLS.iter().enumerate().map(|(i, (_, name))| {
+        let width = measure_text(name, None, 36, 1.0).width + 24.0;
+        let button = TextButton::new(x, 10.0, width, name.to_string(), i.to_string());
+        x += width + 5.0;
+        button
+    }).collect();
+    let mut play_button = TextButton::new(x + 15.0, 10.0, 90.0, "Play".to_owned(), "play".to_owned());
+    let mut save_button = TextButton::new(x + 110.0, 10.0, 90.0, "Save".to_owned(), "save".to_owned());
+
+    let mut selected: Option<(Object, Handle)> = None;
+    let mut dragging = false;
+    let mut last_position = Vec2::ZERO;
+    // Where the wall or mover being drawn started
+    let mut drawing_from: Option<Vec2> = None;
+    // The points of the polygon being placed
+    let mut polygon: Vec<Vec2> = Vec::new();
+    let mut unsaved = false;
+    let mut uploading: Option<Pending<CommunityLevelInfo>> = None;
+
+    let mut scale = 1.0;
+    let mut cam = Camera2D {
+        target: vec2(600.0, 0.0),
+        ..Default::default()
+    };
+    let mut last_mouse = Vec2::from(mouse_position());
+
+    // Don't let the click that opened the editor place anything
+    next_frame().await;
+
+    loop {
+        cam.zoom = vec2(scale / WINDOW_WIDTH * 2.0, scale / WINDOW_HEIGHT * 2.0);
+        set_camera(&cam);
+        clear_background(BLACK);
+
+        let save = |level: &EditorLevel| match save_editor_level(directory, level) {
+            Ok(()) => {
+                toast(format!("Saved {}", level.name));
+                true
+            }
+            Err(e) => {
+                toast(format!("Couldn't save {} ({})", level.name, e));
+                false
+            }
+        };
+
+        if is_key_pressed(keys.leave) {
+            if !polygon.is_empty() {
+                polygon.clear();
+            } else {
+                if unsaved {
+                    save(&level);
+                }
+                break;
+            }
+        }
+
+        // Moving around: right drag or the arrow keys to pan, the wheel to zoom
+        let mouse = Vec2::from(mouse_position());
+        if is_mouse_button_down(MouseButton::Right) {
+            cam.target -= (mouse - last_mouse) / scale * vec2(WINDOW_WIDTH / screen_width(), WINDOW_HEIGHT / screen_height());
+        }
+        last_mouse = mouse;
+        let pan = 800.0 / scale * get_frame_time();
+        if is_key_down(KeyCode::Left) { cam.target.x -= pan; }
+        if is_key_down(KeyCode::Right) { cam.target.x += pan; }
+        if is_key_down(KeyCode::Up) { cam.target.y -= pan; }
+        if is_key_down(KeyCode::Down) { cam.target.y += pan; }
+        let wheel = mouse_wheel().1;
+        if wheel > 0.0 {
+            scale = (scale * 1.1f32).min(3.0);
+        } else if wheel < 0.0 {
+            scale = (scale / 1.1f32).max(0.2);
+        }
+        cam.zoom = vec2(scale / WINDOW_WIDTH * 2.0, scale / WINDOW_HEIGHT * 2.0);
+        let position = cam.screen_to_world(mouse);
+        let snapped = snap(position);
+        let handle_size = 10.0 / scale;
+
+        for (i, key) in [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6, KeyCode::Key7].iter().enumerate() {
+            if is_key_pressed(*key) {
+                tool = TOOLS[i].0;
+                polygon.clear();
+            }
+        }
+        if is_key_pressed(KeyCode::Tab) {
+            tool = Tool::Portal;
+            portal_choice = (portal_choice + 1) % PORTAL_CHOICES.len();
+        }
+        if is_key_pressed(KeyCode::S) && save(&level) {
+            unsaved = false;
+        }
+        if is_key_pressed(KeyCode::U) && uploading.is_none() {
+            match level.world.validate() {
+                Ok(()) => {
+                    let upload = Upload {
+                        title: level.name.clone(),
+                        difficulty: level.difficulty,
+                        world: serde_json::to_value(&level.world).unwrap(),
+                    };
+                    let (server_url, directory, username) = (server_url.to_owned(), directory.to_owned(), username.to_owned());
+                    uploading = Some(network.run(move || upload_level(&server_url, &directory, &username, &upload)));
+                }
+                Err(_) => toast("Fix the problems first, the server won't take it like this"),
+            }
+        }
+        if let Some(pending) = &uploading {
+            match pending.poll() {
+                Loading::Waiting => {}
+                Loading::Done(uploaded) => {
+                    toast(format!("Uploaded {}, it's in the community levels now", uploaded.title));
+                    uploading = None;
+                }
+                Loading::Failed(e) => {
+                    toast(format!("Couldn't upload {}: {}", level.name, e));
+                    uploading = None;
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::LeftBracket) && level.difficulty > 1 {
+            level.difficulty -= 1;
+            unsaved = true;
+        }
+        if is_key_pressed(KeyCode::RightBracket) && level.difficulty < 5 {
+            level.difficulty += 1;
+            unsaved = true;
+        }
+
+        if let Some((object, handle)) = selected {
+            if is_key_pressed(KeyCode::Delete) || is_key_pressed(KeyCode::Backspace) {
+                selected = if delete(&mut level.world, object, handle) { None } else { Some((object, Handle::Body)) };
+                dragging = false;
+                unsaved = true;
+            } else if let (Object::Polygon(i), Handle::Vertex(j), true) = (object, handle, is_key_pressed(KeyCode::V)) {
+                // Splits the edge after the grabbed point
+                let points = &mut level.world.poly_objects[i].points;
+                let next = to_vec(points[(j + 1) % points.len()]);
+                points.insert(j + 1, to_point(snap((to_vec(points[j]) + next) / 2.0)));
+                selected = Some((object, Handle::Vertex(j + 1)));
+                unsaved = true;
+            } else {
+                let change = if is_key_pressed(KeyCode::Equal) { 1.0 } else if is_key_pressed(KeyCode::Minus) { -1.0 } else { 0.0 };
+                if change != 0.0 {
+                    match object {
+                        Object::Mover(i) => {
+                            let o = &mut level.world.moving_objects[i];
+                            o.speed = (o.speed + change * 0.5).max(0.5);
+                        }
+                        Object::SpeedPortal(i) => {
+                            let o = &mut level.world.speed_increases[i];
+                            o.speed_change = (o.speed_change + change * 0.25).max(0.25);
+                        }
+                        Object::Portal(i) => {
+                            let o = &mut level.world.portals[i];
+                            o.kind = change_portal(o.kind, change);
+                        }
+                        _ => {}
+                    }
+                    unsaved = true;
+                }
+            }
+        }
+
+        // The toolbar gets the clicks that land on it
+        let mut toolbar_clicked = false;
+        for (i, button) in tool_buttons.iter_mut().enumerate() {
+            if button.update() {
+                tool = TOOLS[i].0;
+                polygon.clear();
+                toolbar_clicked = true;
+            }
+        }
+        let play_from = if play_button.update() {
+            toolbar_clicked = true;
+            Some(world::SPAWN)
+        } else if is_key_pressed(KeyCode::P) {
+            Some(to_point(snapped))
+        } else {
+            None
+        };
+        if save_button.update() {
+            toolbar_clicked = true;
+            if save(&level) {
+                unsaved = false;
+            }
+        }
+        let over_toolbar = toolbar_clicked || mouse.y < 70.0;
+
+        if is_mouse_button_pressed(MouseButton::Left) && !over_toolbar {
+            match tool {
+                Tool::Select => {
+                    let current = selected.map(|(object, _)| object);
+                    selected = pick(&level.world, position, current, handle_size);
+                    dragging = selected.is_some();
+                    last_position = snapped;
+                }
+                Tool::Wall | Tool::Mover => drawing_from = Some(snapped),
+                Tool::Polygon => {
+                    // Clicking the first point again closes it
+                    if polygon.len() >= 3 && (position - polygon[0]).abs().max_element() <= handle_size {
+                        level.world.poly_objects.push(world::Polygon { points: polygon.drain(..).map(to_point).collect() });
+                        selected = Some((Object::Polygon(level.world.poly_objects.len() - 1), Handle::Body));
+                        unsaved = true;
+                    } else {
+                        polygon.push(snapped);
+                    }
+                }
+                Tool::Portal => {
+                    match PORTAL_CHOICES[portal_choice].1 {
+                        None => {
+                            level.world.speed_increases.push(world::SpeedPortal { x: snapped.x, y: snapped.y, speed_change: 2.0 });
+                            selected = Some((Object::SpeedPortal(level.world.speed_increases.len() - 1), Handle::Body));
+                        }
+                        Some(kind) => {
+                            level.world.portals.push(world::Portal { x: snapped.x, y: snapped.y, kind });
+                            selected = Some((Object::Portal(level.world.portals.len() - 1), Handle::Body));
+                        }
+                    }
+                    unsaved = true;
+                }
+                // There's only one, so this moves it if there already is one
+                Tool::Finish => {
+                    level.world.finish = Some(world::FinishLine { x: snapped.x });
+                    selected = Some((Object::Finish, Handle::Body));
+                    unsaved = true;
+                }
+                Tool::Checkpoint => {
+                    level.world.checkpoints.push(world::Checkpoint { x: snapped.x });
+                    selected = Some((Object::Checkpoint(level.world.checkpoints.len() - 1), Handle::Body));
+                    unsaved = true;
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::Enter) && polygon.len() >= 3 {
+            level.world.poly_objects.push(world::Polygon { points: polygon.drain(..).map(to_point).collect() });
+            selected = Some((Object::Polygon(level.world.poly_objects.len() - 1), Handle::Body));
+            unsaved = true;
+        }
+
+        if dragging && is_mouse_button_down(MouseButton::Left) {
+            if let Some((object, handle)) = selected {
+                let delta = snapped - last_position;
+                if delta != Vec2::ZERO {
+                    drag(&mut level.world, object, handle, snapped, delta);
+                    last_position = snapped;
+                    unsaved = true;
+                }
+            }
+        }
+        if is_mouse_button_released(MouseButton::Left) {
+            dragging = false;
+            if let Some(from) = drawing_from.take() {
+                let rect = rect_between(from, snapped);
+                if rect.width > 0.0 && rect.height > 0.0 {
+                    if tool == Tool::Mover {
+                        level.world.moving_objects.push(world::MovingObject {
+                            from: world::Point { x: rect.x, y: rect.y },
+                            to: world::Point { x: rect.x + GRID * 4.0, y: rect.y },
+                            width: rect.width,
+                            height: rect.height,
+                            speed: 3.0,
+                        });
+                        selected = Some((Object::Mover(level.world.moving_objects.len() - 1), Handle::Body));
+                    } else {
+                        level.world.objects.push(rect);
+                        selected = Some((Object::Wall(level.world.objects.len() - 1), Handle::Body));
+                    }
+                    unsaved = true;
+                }
+            }
+        }
 
+        // The grid, only where the camera can see
+        let top_left = cam.screen_to_world(vec2(0.0, 0.0));
+        let bottom_right = cam.screen_to_world(vec2(screen_width(), screen_height()));
+        let line = 1.0 / scale;
+        let grid_color = Color::new(1.0, 1.0, 1.0, 0.06);
+        let mut x = (top_left.x / GRID).floor() * GRID;
+        while x <= bottom_right.x {
+            draw_line(x, top_left.y, x, bottom_right.y, line, grid_color);
+            x += GRID;
+        }
+        let mut y = (top_left.y / GRID).floor() * GRID;
+        while y <= bottom_right.y {
+            draw_line(top_left.x, y, bottom_right.x, y, line, grid_color);
+            y += GRID;
+        }
+
+        draw_player(&Player::new(world::SPAWN.x, world::SPAWN.y), &textures.player);
+        draw_world(&World::from_def(&level.world), textures);
+        // Where the movers go, they'd be invisible otherwise
+        for o in &level.world.moving_objects {
+            let (from, to) = (to_vec(o.from), to_vec(o.to));
+            let half = vec2(o.width, o.height) / 2.0;
+            draw_line(from.x + half.x, from.y + half.y, to.x + half.x, to.y + half.y, 2.0 * line, SKYBLUE);
+            draw_outline(&rect_points(o.to.x, o.to.y, o.width, o.height), 2.0 * line, Color::new(0.4, 0.75, 1.0, 0.5));
+        }
+
+        if let Some((object, grabbed)) = selected {
+            draw_outline(&object_outline(&level.world, object), 3.0 * line, GOLD);
+            for (handle, at) in handles(&level.world, object) {
+                let color = if handle == grabbed { GOLD } else { WHITE };
+                draw_rectangle(at.x - handle_size / 2.0, at.y - handle_size / 2.0, handle_size, handle_size, color);
+            }
+        }
+        if let Some(from) = drawing_from {
+            let rect = rect_between(from, snapped);
+            draw_outline(&rect_points(rect.x, rect.y, rect.width, rect.height), 2.0 * line, GOLD);
+        }
+        if !polygon.is_empty() {
+            for pair in polygon.windows(2) {
+                draw_line(pair[0].x, pair[0].y, pair[1].x, pair[1].y, 2.0 * line, GOLD);
+            }
+            let last = polygon[polygon.len() - 1];
+            draw_line(last.x, last.y, snapped.x, snapped.y, line, GRAY);
+            for point in &polygon {
+                draw_rectangle(point.x - handle_size / 2.0, point.y - handle_size / 2.0, handle_size, handle_size, WHITE);
+            }
+        }
+        draw_circle(snapped.x, snapped.y, 3.0 * line, WHITE);
+
+        set_default_camera();
+
+        for (i, button) in tool_buttons.iter().enumerate() {
+            button.draw(TOOLS[i].0 == tool);
+        }
+        play_button.draw(false);
+        save_button.draw(false);
+
+        draw_text(
+            &format!("{} ({}){}", level.name, "*".repeat(level.difficulty as usize), if unsaved { " - not saved" } else { "" }),
+            10.0, 100.0, 30.0, WHITE,
+        );
+        if let Some((object, _)) = selected {
+            draw_text(&describe(&level.world, object), 10.0, 135.0, 24.0, GOLD);
+        } else if tool == Tool::Portal {
+            draw_text(&format!("Placing a {} (Tab to change)", PORTAL_CHOICES[portal_choice].0.to_lowercase()), 10.0, 135.0, 24.0, GRAY);
+        }
+        if uploading.is_some() {
+            draw_text("Uploading...", save_button.hitbox.x + 100.0, 42.0, 30.0, GRAY);
+        }
+        // It can be saved like this, but the server won't take it
+        if let Err(error) = level.world.validate() {
+            for (i, problem) in error.problems.iter().take(5).enumerate() {
+                draw_text(&problem.to_string(), 10.0, 170.0 + i as f32 * 28.0, 24.0, RED);
+            }
+        }
+        draw_text(
+            &format!(
+                "Right drag or arrows: move around, wheel: zoom, Delete: remove, P: play from the mouse, [ ]: difficulty, S: save, U: upload, {}: save and leave",
+                key_name(keys.leave),
+            ),
+            10.0, screen_height() - 20.0, 20.0, GRAY,
+        );
+
+        if let Some(start) = play_from {
+            practice(&level.name, &level.world, start, textures, keys).await;
+        }
+
+        draw_toasts();
         next_frame().await;
     }
 }
 
-async fn game(world_res: String, player_texture: Texture2D, wall_texture: Texture2D, movingplatform_texture: Texture2D, speedportal_texture: Texture2D) -> u32 {
-    let mut player = Player::new(0.0, 0.0);
+// Plays a level without recording or sending anything, starting from anywhere. The editor tries
+// levels out with it, and it's practice mode from the title screen. The level's checkpoints are
+// saved when the player gets past them, C drops one wherever the player is and X takes the last
+// one away. Dying goes back to the last one, R goes back to the start
+async fn practice(level_name: &str, level: &world::World, start: world::Point, textures: &Textures, keys: &Keys) {
+    let mut sim = Simulation::starting_at(level, start);
+    let mut previous = sim.clone();
+    let mut timestep = FixedTimestep::new();
+    let mut pending_toggles: u32 = 0;
+    let mut deaths = 0;
+    let mut checkpoints: Vec<Snapshot> = Vec::new();
+
+    let mut cam = Camera2D {
+        zoom: vec2(1.0 / WINDOW_WIDTH * 2.0, 1.0 / WINDOW_HEIGHT * 2.0),
+        ..Default::default()
+    };
+
+    // Don't let the click on the play button flip the player
+    next_frame().await;
 
-    let mut world = World::from_json(world_res.as_str());
+    loop {
+        set_camera(&cam);
+        clear_background(BLACK);
+
+        if is_key_pressed(keys.leave) {
+            break;
+        }
+        if is_mouse_button_pressed(MouseButton::Left) || is_key_pressed(keys.jump) {
+            pending_toggles += 1;
+        }
+        if is_key_pressed(KeyCode::C) {
+            checkpoints.push(sim.snapshot());
+        }
+        if is_key_pressed(KeyCode::X) {
+            checkpoints.pop();
+        }
+        if is_key_pressed(KeyCode::R) {
+            checkpoints.clear();
+            sim.restart();
+            previous = sim.clone();
+            timestep.reset();
+        }
+
+        for _ in 0..timestep.advance(get_frame_time()) {
+            previous = sim.clone();
+            let input = Input { toggle: pending_toggles > 0 };
+            pending_toggles = pending_toggles.saturating_sub(1);
+            match sim.step(input) {
+                Step::Alive => {}
+                Step::Checkpoint => checkpoints.push(sim.snapshot()),
+                Step::Died => {
+                    deaths += 1;
+                    match checkpoints.last() {
+                        Some(checkpoint) => sim.restore(checkpoint),
+                        None => sim.restart(),
+                    }
+                    previous = sim.clone();
+                    timestep.reset();
+                    break;
+                }
+                // The checkpoints were for getting here, so it starts over without them
+                Step::Finished => {
+                    toast(format!("Finished in {}s with {} deaths", round(ticks_to_seconds(sim.tick), 2), deaths));
+                    deaths = 0;
+                    checkpoints.clear();
+                    sim.restart();
+                    previous = sim.clone();
+                    timestep.reset();
+                    break;
+                }
+            }
+        }
+
+        let (player, world) = interpolate(&previous, &sim, timestep.alpha());
+        cam.target.x = camera_x(&player);
+
+        for checkpoint in &checkpoints {
+            draw_dropped_checkpoint(&checkpoint.player);
+        }
+        draw_player(&player, &textures.player);
+        draw_world(&world, textures);
+
+        set_default_camera();
+
+        draw_text(&format!("Practicing {} - {} to go back", level_name, key_name(keys.leave)), 10.0, 50.0, 30.0, WHITE);
+        draw_text(&format!("Time: {}  Deaths: {}  Checkpoints: {}", round(ticks_to_seconds(sim.tick), 2), deaths, checkpoints.len()), 10.0, 100.0, 30.0, WHITE);
+        draw_text("C: drop a checkpoint, X: remove the last one, R: start over. Nothing here goes on the leaderboard", 10.0, screen_height() - 20.0, 20.0, GRAY);
+
+        draw_toasts();
+        next_frame().await
+    }
+}
+
+// Shown when the finish line is reached. Returns whether to play again
+async fn results(level_name: &str, ticks: u32, attempts: u32, previous_best: Option<u32>, keys: &Keys) -> bool {
+    // Don't let a click or jump from the level count here
+    next_frame().await;
+
+    loop {
+        set_default_camera();
+        clear_background(BLACK);
+
+        if is_key_pressed(keys.leave) {
+            return false;
+        }
+        if is_key_pressed(keys.jump) || is_key_pressed(KeyCode::Enter) {
+            return true;
+        }
+
+        draw_text(&format!("You finished {}!", level_name), 100.0, 150.0, 72.0, GOLD);
+        draw_text(&format!("Time: {}s", round(ticks_to_seconds(ticks), 2)), 100.0, 260.0, 48.0, WHITE);
+        draw_text(&format!("Attempts: {}", attempts), 100.0, 330.0, 48.0, WHITE);
+        let (record, color) = match previous_best {
+            None => ("Your first time reaching the end!".to_owned(), GOLD),
+            Some(best) if ticks < best => (format!("New personal best, {}s faster than before!", round(ticks_to_seconds(best - ticks), 2)), GOLD),
+            Some(best) => (format!("Your personal best is {}s", round(ticks_to_seconds(best), 2)), SKYBLUE),
+        };
+        draw_text(&record, 100.0, 420.0, 36.0, color);
+        draw_text(&format!("{} to play again, {} to go back", key_name(keys.jump), key_name(keys.leave)), 100.0, 800.0, 30.0, GRAY);
+
+        draw_toasts();
+        next_frame().await;
+    }
+}
+
+// Returns the best attempt
+// `submit` is called with every attempt as soon as it ends
+async fn game(
+    level_id: &str,
+    level_name: &str,
+    level: &world::World,
+    directory: &str,
+    textures: &Textures,
+    keys: &Keys,
+    submit: &mut impl FnMut(&Replay),
+) -> Session {
+    let mut sim = Simulation::new(level);
+    let mut previous = sim.clone();
+    let mut timestep = FixedTimestep::new();
+    let mut pending_toggles: u32 = 0;
+    let mut replay = Replay::new(level_id);
 
     let mut cam = Camera2D {
         zoom: vec2(1.0 / WINDOW_WIDTH * 2.0, 1.0 / WINDOW_HEIGHT * 2.0),
@@ -1086,8 +3018,11 @@ async fn game(world_res: String, player_texture: Texture2D, wall_texture: Textur
     };
 
     let mut attempts = 0;
-    let mut score = 0;
+    let mut playtime = 0;
     let mut best_score = 0;
+    let mut best_replay = Replay::new(level_id);
+    // The time it took and the best time before, when the finish line was reached this frame
+    let mut finished: Option<(u32, Option<u32>)> = None;
 
     let mut bg_color = BLACK;
     
@@ -1095,7 +3030,17 @@ async fn game(world_res: String, player_texture: Texture2D, wall_texture: Textur
         set_camera(&cam);
         clear_background(bg_color);
 
-        if is_key_pressed(KeyCode::Escape) {
+        if is_key_pressed(keys.leave) {
+            save_replay(directory, &replay);
+            // Leaving halfway through still counts as an attempt
+            if replay.ticks > 0 {
+                attempts += 1;
+                playtime += replay.ticks;
+                submit(&replay);
+            }
+            if replay.ticks > best_replay.ticks {
+                best_replay = replay;
+            }
             break;
         }
 
@@ -1108,50 +3053,96 @@ async fn game(world_res: String, player_texture: Texture2D, wall_texture: Textur
             bg_color.b = round(bg_color.b, 4);
         }
         
-        if is_mouse_button_pressed(MouseButton::Left) || is_key_pressed(KeyCode::Space) {
+        if is_mouse_button_pressed(MouseButton::Left) || is_key_pressed(keys.jump) {
             bg_color = Color::new(0.125, 0.125, 0.25, 1.0);
-        }
+            pending_toggles += 1;
+        }
+
+        for _ in 0..timestep.advance(get_frame_time()) {
+            previous = sim.clone();
+            // Clicks go to the next tick, even if there wasn't one this frame
+            let input = Input { toggle: pending_toggles > 0 };
+            pending_toggles = pending_toggles.saturating_sub(1);
+
+            let step = sim.step(input);
+            if step == Step::Died {
+                save_replay(directory, &replay);
+                let attempt = std::mem::replace(&mut replay, Replay::new(level_id));
+                attempts += 1;
+                playtime += attempt.ticks;
+                submit(&attempt);
+                if attempt.ticks > best_replay.ticks {
+                    best_replay = attempt;
+                }
+                sim.restart();
+                previous = sim.clone();
+                timestep.reset();
+                break;
+            }
+            replay.record(input);
+
+            if step == Step::Finished {
+                save_replay(directory, &replay);
+                let attempt = std::mem::replace(&mut replay, Replay::new(level_id));
+                attempts += 1;
+                playtime += attempt.ticks;
+                submit(&attempt);
+
+                let mut records = load_records(directory);
+                let previous_best = records.get(level_id).copied();
+                if previous_best.is_none_or(|best| attempt.ticks < best) {
+                    records.insert(level_id.to_owned(), attempt.ticks);
+                    save_records(directory, &records);
+                }
+                finished = Some((attempt.ticks, previous_best));
 
-        let dead = player.update(&world);
+                if attempt.ticks > best_replay.ticks {
+                    best_replay = attempt;
+                }
+                break;
+            }
+        }
 
-        if dead {
-            player = Player::new(0.0, 0.0);
-            for object in &mut world.speed_increases {
-                object.used = false;
+        if let Some((ticks, previous_best)) = finished.take() {
+            if !results(level_name, ticks, attempts, previous_best, keys).await {
+                break;
             }
-            attempts += 1;
-            score = 0;
-            next_frame().await;
+            sim.restart();
+            previous = sim.clone();
+            timestep.reset();
         }
-        
-        world.update(&mut player);
 
-        cam.target.x = player.x + 200.;
+        let (player, world) = interpolate(&previous, &sim, timestep.alpha());
+
+        cam.target.x = camera_x(&player);
 
         draw_text_ex("Hardest Game Ever", 0.0, 0.0, TextParams {
             font_size: 48,
             rotation: 3.0f32.to_radians(),
             ..Default::default()
         });
-        draw_text("Use the mouse or space bar to change direction", -50.0, 60.0, 24.0, WHITE);
+        draw_text(&format!("Use the mouse or {} to change direction", key_name(keys.jump)), -50.0, 60.0, 24.0, WHITE);
         draw_text(&format!("Attempts: {}", attempts), 0.0, 100.0, 36.0, WHITE);
 
-        player.draw(&player_texture);
-        world.draw(&wall_texture, &movingplatform_texture, &speedportal_texture).await;
+        draw_player(&player, &textures.player);
+        draw_world(&world, textures);
 
         set_default_camera();
 
-        draw_text(&format!("Score: {}", round(score as f32 / 60.0, 2)), 10.0, 50.0, 30.0, WHITE);
-        draw_text(&format!("Best Score: {}", round(best_score as f32 / 60.0, 2)), 10.0, 100.0, 30.0, WHITE);
-
-        score += 1;
-
-        if score > best_score {
-            best_score = score;
+        if sim.tick > best_score {
+            best_score = sim.tick;
         }
 
+        draw_text(&format!("Score: {}", round(ticks_to_seconds(sim.tick), 2)), 10.0, 50.0, 30.0, WHITE);
+        draw_text(&format!("Best Score: {}", round(ticks_to_seconds(best_score), 2)), 10.0, 100.0, 30.0, WHITE);
+
+        draw_toasts();
         next_frame().await
     }
 
-    best_score
+    Session {
+        best: best_replay,
+        attempts,
+        playtime,
+    }
 }
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+use crate::world::World;
+
+// A level a player made and uploaded. They're stored like scores, unlike the ones in the levels
+// directory, and played and ranked just like them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommunityLevel {
+    pub id: String,
+    pub title: String,
+    pub author_id: String,
+    pub difficulty: u32,
+    // Seconds since the unix epoch
+    pub created: u64,
+    pub world: World,
+}
+
+// What the client sends to POST /levels. The world is checked before anything is stored
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Upload {
+    pub title: String,
+    pub difficulty: u32,
+    pub world: serde_json::Value,
+}
+
+// A community level without its world, for browsing
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommunityLevelInfo {
+    pub id: String,
+    pub title: String,
+    // Whatever the author is called now
+    pub author: String,
+    pub difficulty: u32,
+    pub created: u64,
+    // How many runs were submitted on it
+    pub plays: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+    #[default]
+    Newest,
+    MostPlayed,
+}
+
+pub const MAX_TITLE_LENGTH: usize = 64;
+// Bigger levels than this are refused, so nobody can fill the server up with one
+pub const MAX_OBJECTS: usize = 2000;
+
+pub fn object_count(world: &World) -> usize {
+    world.objects.len()
+        + world.poly_objects.iter().map(|o| o.points.len()).sum::<usize>()
+        + world.moving_objects.len()
+        + world.speed_increases.len()
+        + world.checkpoints.len()
+        + world.portals.len()
+}
+
+// Whether `search` is somewhere in the title, ignoring case
+pub fn matches(level: &CommunityLevelInfo, search: &str) -> bool {
+    level.title.to_lowercase().contains(&search.trim().to_lowercase())
+}
+
+pub fn sort(levels: &mut [CommunityLevelInfo], sort: Sort) {
+    match sort {
+        Sort::Newest => levels.sort_by_key(|l| Reverse(l.created)),
+        Sort::MostPlayed => levels.sort_by_key(|l| (Reverse(l.plays), Reverse(l.created))),
+    }
+}
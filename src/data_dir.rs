@@ -0,0 +1,92 @@
+use std::{env, path::PathBuf};
+
+// Where the client keeps the username, settings, replays and everything else it saves:
+//   DATA_DIR, if it's set
+//   Linux and friends - $XDG_DATA_HOME/HardestGameEver, or ~/.local/share/HardestGameEver
+//   Windows           - %APPDATA%\HardestGameEver
+//   macOS             - ~/Library/Application Support/HardestGameEver
+// and a HardestGameEver directory right here if none of that can be figured out
+pub fn data_directory() -> PathBuf {
+    resolve(|name| env::var(name).ok()).unwrap_or(PathBuf::from(APP_DIRECTORY))
+}
+
+const APP_DIRECTORY: &str = "HardestGameEver";
+
+// `var` looks up env vars, so this can be tried out with made up environments
+pub fn resolve(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    // Empty env vars count as not set, like most programs treat them
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+
+    if let Some(dir) = var("DATA_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if cfg!(target_os = "windows") {
+        resolve_windows(var)
+    } else if cfg!(target_os = "macos") {
+        resolve_macos(var)
+    } else {
+        resolve_xdg(var)
+    }
+}
+
+pub fn resolve_xdg(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    // The spec says relative paths in XDG_DATA_HOME are invalid and should be ignored
+    if let Some(dir) = var("XDG_DATA_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        return Some(dir.join(APP_DIRECTORY));
+    }
+    let home = var("HOME")?;
+    Some(PathBuf::from(home).join(".local").join("share").join(APP_DIRECTORY))
+}
+
+pub fn resolve_windows(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    if let Some(dir) = var("APPDATA") {
+        return Some(PathBuf::from(dir).join(APP_DIRECTORY));
+    }
+    let profile = var("USERPROFILE")?;
+    Some(PathBuf::from(profile).join("AppData").join("Roaming").join(APP_DIRECTORY))
+}
+
+pub fn resolve_macos(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let home = var("HOME")?;
+    Some(PathBuf::from(home).join("Library").join("Application Support").join(APP_DIRECTORY))
+}
+
+// The Linux rules, which are the ones `resolve` follows everywhere but Windows and macOS
+#[cfg(all(test, not(any(target_os = "windows", target_os = "macos"))))]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| vars.iter().find(|(n, _)| *n == name).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn data_dir_wins() {
+        let vars = [("DATA_DIR", "/games/hge"), ("XDG_DATA_HOME", "/xdg"), ("HOME", "/home/me")];
+        assert_eq!(resolve(env(&vars)), Some(PathBuf::from("/games/hge")));
+    }
+
+    #[test]
+    fn absolute_xdg_data_home_is_used() {
+        let vars = [("XDG_DATA_HOME", "/xdg"), ("HOME", "/home/me")];
+        assert_eq!(resolve(env(&vars)), Some(PathBuf::from("/xdg/HardestGameEver")));
+    }
+
+    #[test]
+    fn relative_or_empty_xdg_data_home_is_ignored() {
+        let home = Some(PathBuf::from("/home/me/.local/share/HardestGameEver"));
+        assert_eq!(resolve(env(&[("XDG_DATA_HOME", "xdg"), ("HOME", "/home/me")])), home);
+        assert_eq!(resolve(env(&[("XDG_DATA_HOME", ""), ("HOME", "/home/me")])), home);
+    }
+
+    #[test]
+    fn home_falls_back_to_local_share() {
+        assert_eq!(resolve(env(&[("HOME", "/home/me")])), Some(PathBuf::from("/home/me/.local/share/HardestGameEver")));
+    }
+
+    #[test]
+    fn nothing_set_is_none() {
+        assert_eq!(resolve(env(&[])), None);
+        assert_eq!(resolve(env(&[("DATA_DIR", ""), ("HOME", "")])), None);
+    }
+}
@@ -0,0 +1,380 @@
+use macroquad::math::{vec2, Vec2};
+
+use crate::{geometry::point_in_polygon, levels::LevelFile, world};
+
+// What the level editor does to a level, apart from drawing it
+
+// Everything placed in the editor snaps to this
+pub const GRID: f32 = 25.0;
+
+pub fn snap(position: Vec2) -> Vec2 {
+    (position / GRID).round() * GRID
+}
+
+pub fn to_vec(point: world::Point) -> Vec2 {
+    vec2(point.x, point.y)
+}
+
+pub fn to_point(position: Vec2) -> world::Point {
+    world::Point { x: position.x, y: position.y }
+}
+
+pub struct EditorLevel {
+    pub id: String,
+    pub name: String,
+    pub difficulty: u32,
+    pub world: world::World,
+}
+
+// Reads a level the editor saved, along with what the server wouldn't like about it. The editor
+// shows those so they can be fixed, except polygons with fewer than 3 points: they can't even be
+// picked, so they're dropped
+pub fn parse_editor_level(id: &str, data: &str) -> Result<(EditorLevel, Option<world::LevelError>), String> {
+    let file: LevelFile = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    let mut world: world::World = serde_json::from_value(file.world).map_err(|e| e.to_string())?;
+    let problems = world.validate().err();
+    world.poly_objects.retain(|polygon| polygon.points.len() >= 3);
+    Ok((EditorLevel { id: id.to_owned(), name: file.name, difficulty: file.difficulty, world }, problems))
+}
+
+// A file name for a level, made out of its name
+pub fn editor_level_id(name: &str, levels: &[EditorLevel]) -> String {
+    let mut base: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    base = base.trim_matches('-').to_owned();
+    if base.is_empty() {
+        base = "level".to_owned();
+    }
+    let mut id = base.clone();
+    let mut n = 2;
+    while levels.iter().any(|l| l.id == id) {
+        id = format!("{}-{}", base, n);
+        n += 1;
+    }
+    id
+}
+
+// The finish line is two squares wide. Both it and checkpoints can be grabbed where they're drawn
+pub const FINISH_SQUARE: f32 = 16.0;
+pub const CHECKPOINT_WIDTH: f32 = 6.0;
+
+// Something in the level, by its index in the list it's in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Object {
+    Wall(usize),
+    Polygon(usize),
+    Mover(usize),
+    SpeedPortal(usize),
+    Portal(usize),
+    Finish,
+    Checkpoint(usize),
+}
+
+// Which bit of an object is grabbed. `Corner` is the bottom right one, for resizing, and `End` is
+// where a mover goes to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Handle {
+    Body,
+    Corner,
+    Vertex(usize),
+    End,
+}
+
+// The handles an object has, and where they are
+pub fn handles(level: &world::World, object: Object) -> Vec<(Handle, Vec2)> {
+    match object {
+        Object::Wall(i) => {
+            let o = &level.objects[i];
+            vec![(Handle::Corner, vec2(o.x + o.width, o.y + o.height))]
+        }
+        Object::Polygon(i) => level.poly_objects[i].points.iter().enumerate().map(|(j, p)| (Handle::Vertex(j), to_vec(*p))).collect(),
+        Object::Mover(i) => {
+            let o = &level.moving_objects[i];
+            vec![
+                (Handle::Corner, vec2(o.from.x + o.width, o.from.y + o.height)),
+                (Handle::End, to_vec(o.to)),
+            ]
+        }
+        Object::SpeedPortal(_) | Object::Portal(_) | Object::Finish | Object::Checkpoint(_) => Vec::new(),
+    }
+}
+
+fn in_rect(position: Vec2, x: f32, y: f32, width: f32, height: f32) -> bool {
+    position.x >= x && position.x <= x + width && position.y >= y && position.y <= y + height
+}
+
+// What's under the mouse. The handles of the selected object come first, then whatever is drawn
+// on top
+pub fn pick(level: &world::World, position: Vec2, selected: Option<Object>, handle_size: f32) -> Option<(Object, Handle)> {
+    if let Some(object) = selected {
+        for (handle, at) in handles(level, object) {
+            if (position - at).abs().max_element() <= handle_size {
+                return Some((object, handle));
+            }
+        }
+    }
+    if let Some(finish) = level.finish {
+        if position.x >= finish.x && position.x <= finish.x + FINISH_SQUARE * 2.0 {
+            return Some((Object::Finish, Handle::Body));
+        }
+    }
+    // A bit wider than they're drawn, they'd be hard to click otherwise
+    for (i, o) in level.checkpoints.iter().enumerate().rev() {
+        if (position.x - o.x - CHECKPOINT_WIDTH / 2.0).abs() <= handle_size {
+            return Some((Object::Checkpoint(i), Handle::Body));
+        }
+    }
+    for (i, o) in level.portals.iter().enumerate().rev() {
+        if in_rect(position, o.x, o.y, world::PORTAL_WIDTH, world::PORTAL_HEIGHT) {
+            return Some((Object::Portal(i), Handle::Body));
+        }
+    }
+    for (i, o) in level.speed_increases.iter().enumerate().rev() {
+        if in_rect(position, o.x, o.y, world::PORTAL_WIDTH, world::PORTAL_HEIGHT) {
+            return Some((Object::SpeedPortal(i), Handle::Body));
+        }
+    }
+    for (i, o) in level.moving_objects.iter().enumerate().rev() {
+        if in_rect(position, o.from.x, o.from.y, o.width, o.height) {
+            return Some((Object::Mover(i), Handle::Body));
+        }
+    }
+    for (i, o) in level.poly_objects.iter().enumerate().rev() {
+        let points: Vec<Vec2> = o.points.iter().copied().map(to_vec).collect();
+        if point_in_polygon(position, &points) {
+            return Some((Object::Polygon(i), Handle::Body));
+        }
+    }
+    for (i, o) in level.objects.iter().enumerate().rev() {
+        if in_rect(position, o.x, o.y, o.width, o.height) {
+            return Some((Object::Wall(i), Handle::Body));
+        }
+    }
+    None
+}
+
+// Drags a handle to `position`, or the whole object by `delta` if it's grabbed by the body
+pub fn drag(level: &mut world::World, object: Object, handle: Handle, position: Vec2, delta: Vec2) {
+    let shift = |p: &mut world::Point| {
+        p.x += delta.x;
+        p.y += delta.y;
+    };
+    match (object, handle) {
+        (Object::Wall(i), Handle::Corner) => {
+            let o = &mut level.objects[i];
+            o.width = (position.x - o.x).max(GRID);
+            o.height = (position.y - o.y).max(GRID);
+        }
+        (Object::Wall(i), _) => {
+            let o = &mut level.objects[i];
+            o.x += delta.x;
+            o.y += delta.y;
+        }
+        (Object::Polygon(i), Handle::Vertex(j)) => level.poly_objects[i].points[j] = to_point(position),
+        (Object::Polygon(i), _) => level.poly_objects[i].points.iter_mut().for_each(shift),
+        (Object::Mover(i), Handle::Corner) => {
+            let o = &mut level.moving_objects[i];
+            o.width = (position.x - o.from.x).max(GRID);
+            o.height = (position.y - o.from.y).max(GRID);
+        }
+        (Object::Mover(i), Handle::End) => level.moving_objects[i].to = to_point(position),
+        // Moving the whole thing takes the path along
+        (Object::Mover(i), _) => {
+            let o = &mut level.moving_objects[i];
+            shift(&mut o.from);
+            shift(&mut o.to);
+        }
+        (Object::SpeedPortal(i), _) => {
+            let o = &mut level.speed_increases[i];
+            o.x += delta.x;
+            o.y += delta.y;
+        }
+        (Object::Portal(i), _) => {
+            let o = &mut level.portals[i];
+            o.x += delta.x;
+            o.y += delta.y;
+        }
+        (Object::Finish, _) => {
+            if let Some(finish) = &mut level.finish {
+                finish.x += delta.x;
+            }
+        }
+        (Object::Checkpoint(i), _) => level.checkpoints[i].x += delta.x,
+    }
+}
+
+// Deletes the object, or just the vertex if one of a polygon's is grabbed. Returns whether the
+// whole object is gone
+pub fn delete(level: &mut world::World, object: Object, handle: Handle) -> bool {
+    match (object, handle) {
+        (Object::Polygon(i), Handle::Vertex(j)) if level.poly_objects[i].points.len() > 3 => {
+            level.poly_objects[i].points.remove(j);
+            return false;
+        }
+        (Object::Wall(i), _) => {
+            level.objects.remove(i);
+        }
+        (Object::Polygon(i), _) => {
+            level.poly_objects.remove(i);
+        }
+        (Object::Mover(i), _) => {
+            level.moving_objects.remove(i);
+        }
+        (Object::SpeedPortal(i), _) => {
+            level.speed_increases.remove(i);
+        }
+        (Object::Portal(i), _) => {
+            level.portals.remove(i);
+        }
+        (Object::Finish, _) => level.finish = None,
+        (Object::Checkpoint(i), _) => {
+            level.checkpoints.remove(i);
+        }
+    }
+    true
+}
+
+// The rectangle between two corners, whichever way round they are
+pub fn rect_between(a: Vec2, b: Vec2) -> world::Rect {
+    let min = a.min(b);
+    let size = (a - b).abs();
+    world::Rect { x: min.x, y: min.y, width: size.x, height: size.y }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A wall with a mover over its bottom right corner, and a bit of everything else further along
+    fn level() -> world::World {
+        let point = |x, y| world::Point { x, y };
+        world::World {
+            objects: vec![world::Rect { x: 0.0, y: 0.0, width: 100.0, height: 100.0 }],
+            poly_objects: vec![world::Polygon { points: vec![point(300.0, 0.0), point(400.0, 0.0), point(400.0, 100.0), point(300.0, 100.0)] }],
+            moving_objects: vec![world::MovingObject { from: point(50.0, 50.0), to: point(50.0, 250.0), width: 100.0, height: 100.0, speed: 1.0 }],
+            speed_increases: vec![world::SpeedPortal { x: 500.0, y: 0.0, speed_change: 2.0 }],
+            finish: Some(world::FinishLine { x: 1000.0 }),
+            checkpoints: vec![world::Checkpoint { x: 800.0 }],
+            portals: vec![world::Portal { x: 600.0, y: 0.0, kind: world::PortalKind::Gravity { inverted: true } }],
+        }
+    }
+
+    #[test]
+    fn picks_whats_on_top() {
+        let level = level();
+        let pick = |x, y| pick(&level, vec2(x, y), None, 10.0).map(|(object, _)| object);
+        assert_eq!(pick(10.0, 10.0), Some(Object::Wall(0)));
+        assert_eq!(pick(75.0, 75.0), Some(Object::Mover(0)));
+        assert_eq!(pick(350.0, 50.0), Some(Object::Polygon(0)));
+        assert_eq!(pick(510.0, 10.0), Some(Object::SpeedPortal(0)));
+        assert_eq!(pick(610.0, 10.0), Some(Object::Portal(0)));
+        // Lines go all the way up and down
+        assert_eq!(pick(805.0, -5000.0), Some(Object::Checkpoint(0)));
+        assert_eq!(pick(1010.0, 5000.0), Some(Object::Finish));
+        assert_eq!(pick(700.0, 500.0), None);
+    }
+
+    #[test]
+    fn picks_handles_of_the_selected_object_first() {
+        let level = level();
+        // The wall's corner is under the mover, but the wall is the one that's selected
+        assert_eq!(pick(&level, vec2(98.0, 103.0), Some(Object::Wall(0)), 10.0), Some((Object::Wall(0), Handle::Corner)));
+        assert_eq!(pick(&level, vec2(98.0, 103.0), None, 10.0), Some((Object::Mover(0), Handle::Body)));
+        assert_eq!(pick(&level, vec2(400.0, 100.0), Some(Object::Polygon(0)), 10.0), Some((Object::Polygon(0), Handle::Vertex(2))));
+        assert_eq!(pick(&level, vec2(50.0, 250.0), Some(Object::Mover(0)), 10.0), Some((Object::Mover(0), Handle::End)));
+        assert!(handles(&level, Object::Finish).is_empty());
+    }
+
+    #[test]
+    fn dragging_the_body_moves_everything() {
+        let mut level = level();
+        let delta = vec2(GRID, -GRID);
+        drag(&mut level, Object::Mover(0), Handle::Body, Vec2::ZERO, delta);
+        let mover = level.moving_objects[0];
+        assert_eq!((mover.from, mover.to), (world::Point { x: 75.0, y: 25.0 }, world::Point { x: 75.0, y: 225.0 }));
+
+        drag(&mut level, Object::Polygon(0), Handle::Body, Vec2::ZERO, delta);
+        assert_eq!(level.poly_objects[0].points[0], world::Point { x: 325.0, y: -25.0 });
+
+        // Lines only go sideways
+        drag(&mut level, Object::Finish, Handle::Body, Vec2::ZERO, delta);
+        assert_eq!(level.finish, Some(world::FinishLine { x: 1025.0 }));
+    }
+
+    #[test]
+    fn dragging_a_handle_moves_just_that() {
+        let mut level = level();
+        drag(&mut level, Object::Wall(0), Handle::Corner, vec2(200.0, 150.0), Vec2::ZERO);
+        assert_eq!(level.objects[0], world::Rect { x: 0.0, y: 0.0, width: 200.0, height: 150.0 });
+        // Never smaller than a grid square, or it'd be gone
+        drag(&mut level, Object::Wall(0), Handle::Corner, vec2(-50.0, -50.0), Vec2::ZERO);
+        assert_eq!((level.objects[0].width, level.objects[0].height), (GRID, GRID));
+
+        drag(&mut level, Object::Mover(0), Handle::End, vec2(300.0, 50.0), Vec2::ZERO);
+        assert_eq!(level.moving_objects[0].from, world::Point { x: 50.0, y: 50.0 });
+        assert_eq!(level.moving_objects[0].to, world::Point { x: 300.0, y: 50.0 });
+
+        drag(&mut level, Object::Polygon(0), Handle::Vertex(1), vec2(450.0, 25.0), Vec2::ZERO);
+        assert_eq!(level.poly_objects[0].points[1], world::Point { x: 450.0, y: 25.0 });
+    }
+
+    #[test]
+    fn deleting_a_vertex_keeps_at_least_a_triangle() {
+        let mut level = level();
+        assert!(!delete(&mut level, Object::Polygon(0), Handle::Vertex(0)));
+        assert_eq!(level.poly_objects[0].points.len(), 3);
+        assert!(delete(&mut level, Object::Polygon(0), Handle::Vertex(0)));
+        assert!(level.poly_objects.is_empty());
+
+        assert!(delete(&mut level, Object::Finish, Handle::Body));
+        assert_eq!(level.finish, None);
+        assert!(delete(&mut level, Object::Checkpoint(0), Handle::Body));
+        assert!(level.checkpoints.is_empty());
+    }
+
+    #[test]
+    fn broken_polygons_cant_be_picked() {
+        let mut level = level();
+        level.poly_objects = vec![world::Polygon { points: Vec::new() }, world::Polygon { points: vec![world::Point { x: 0.0, y: 0.0 }] }];
+        level.objects.clear();
+        level.moving_objects.clear();
+        assert_eq!(pick(&level, vec2(0.0, 0.0), None, 10.0), None);
+    }
+
+    #[test]
+    fn broken_polygons_are_dropped_when_loading() {
+        let file = |world: &world::World| {
+            let file = LevelFile { name: "Broken".to_owned(), difficulty: 2, world: serde_json::to_value(world).unwrap() };
+            serde_json::to_string(&file).unwrap()
+        };
+        let mut broken = world::World::classic();
+        broken.poly_objects.insert(0, world::Polygon { points: Vec::new() });
+        let (loaded, problems) = parse_editor_level("broken", &file(&broken)).unwrap();
+        assert_eq!((loaded.id.as_str(), loaded.name.as_str(), loaded.difficulty), ("broken", "Broken", 2));
+        assert_eq!(loaded.world, world::World::classic());
+        assert_eq!(problems.unwrap().problems, [world::Problem::TooFewPoints { path: "poly_objects[0]".to_owned(), points: 0 }]);
+
+        assert_eq!(parse_editor_level("fine", &file(&world::World::classic())).unwrap().1, None);
+        assert!(parse_editor_level("nope", "{\"name\": \"Nope\"}").is_err());
+    }
+
+    #[test]
+    fn level_ids_come_from_the_name() {
+        let existing = |id: &str| EditorLevel { id: id.to_owned(), name: String::new(), difficulty: 1, world: level() };
+        assert_eq!(editor_level_id("My Level!", &[]), "my-level");
+        assert_eq!(editor_level_id("???", &[]), "level");
+        let levels = [existing("my-level"), existing("my-level-2")];
+        assert_eq!(editor_level_id("My Level", &levels), "my-level-3");
+    }
+
+    #[test]
+    fn rect_between_takes_either_corner() {
+        let rect = world::Rect { x: 0.0, y: 5.0, width: 10.0, height: 15.0 };
+        assert_eq!(rect_between(vec2(10.0, 20.0), vec2(0.0, 5.0)), rect);
+        assert_eq!(rect_between(vec2(0.0, 20.0), vec2(10.0, 5.0)), rect);
+    }
+}
@@ -0,0 +1,44 @@
+use macroquad::math::Vec2;
+
+fn cross(a: Vec2, b: Vec2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+pub fn line_intersect(p1: Vec2, p2: Vec2, q1: Vec2, q2: Vec2) -> bool {
+    let r = p2 - p1;
+    let s = q2 - q1;
+
+    let r_cross_s = cross(r, s);
+    let q_minus_p = q1 - p1;
+
+    if r_cross_s == 0.0 {
+        return false;
+    }
+
+    let t = cross(q_minus_p, s) / r_cross_s;
+    let u = cross(q_minus_p, r) / r_cross_s;
+
+    (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)
+}
+
+pub fn point_in_polygon(point: Vec2, polygon: &[Vec2]) -> bool {
+    // Nothing can be inside a line or a point
+    if polygon.len() < 3 {
+        return false;
+    }
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+
+    for i in 0..polygon.len() {
+        let pi = polygon[i];
+        let pj = polygon[j];
+
+        if ((pi.y > point.y) != (pj.y > point.y))
+            && (point.x < (pj.x - pi.x) * (point.y - pi.y) / (pj.y - pi.y) + pi.x)
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::{env, fs, path::Path};
+
+use crate::world::World;
+
+// The level that /world serves, for clients that don't know about levels yet
+pub const DEFAULT_LEVEL: &str = "classic";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LevelInfo {
+    pub id: String,
+    pub name: String,
+    pub difficulty: u32,
+}
+
+// What a file in the levels directory looks like. The id is the file name without ".json".
+// The client's level editor saves levels the same way, so they can be copied straight over
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LevelFile {
+    pub name: String,
+    pub difficulty: u32,
+    pub world: serde_json::Value,
+}
+
+#[derive(Clone, Debug)]
+pub struct Level {
+    pub info: LevelInfo,
+    pub world: World,
+}
+
+// Loads every level in LEVELS_DIR (defaults to "levels"), easiest first
+pub fn load_levels() -> Vec<Level> {
+    load_levels_from(env::var("LEVELS_DIR").unwrap_or("levels".to_owned()))
+}
+
+pub fn load_levels_from(dir: impl AsRef<Path>) -> Vec<Level> {
+    let dir = dir.as_ref();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Couldn't read the levels directory {} ({})", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut levels = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+
+        let file: LevelFile = match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_str(&data).map_err(|e| e.to_string()))
+        {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Skipping level {} ({})", path.display(), e);
+                continue;
+            }
+        };
+
+        // Never serve a level that would crash the client
+        let world = match World::from_value(file.world) {
+            Ok(world) => world,
+            Err(e) => {
+                eprintln!("Skipping level {} ({})", path.display(), e);
+                continue;
+            }
+        };
+
+        levels.push(Level {
+            info: LevelInfo {
+                id: id.to_owned(),
+                name: file.name,
+                difficulty: file.difficulty,
+            },
+            world,
+        });
+    }
+
+    levels.sort_by(|a, b| {
+        a.info.difficulty.cmp(&b.info.difficulty).then_with(|| a.info.name.cmp(&b.info.name))
+    });
+    levels
+}
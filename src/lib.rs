@@ -0,0 +1,14 @@
+pub mod accounts;
+pub mod assets;
+pub mod community;
+pub mod data_dir;
+pub mod editor;
+pub mod geometry;
+pub mod levels;
+pub mod replay;
+pub mod router;
+pub mod settings;
+pub mod stats;
+pub mod sim;
+pub mod storage;
+pub mod world;
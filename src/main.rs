@@ -1,4 +1,4 @@
-mod router;
+use hardest_game_ever::router;
 
 #[shuttle_runtime::main]
 async fn main() -> shuttle_axum::ShuttleAxum {
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sim::{Input, Simulation, Step},
+    world::World,
+};
+
+// Bump this whenever the physics change, replays recorded before that won't play back the same
+pub const REPLAY_VERSION: u32 = 1;
+
+// A single attempt at a level. Since the game is deterministic, the ticks on which the player
+// changed direction are all that's needed to play it again
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub level: String,
+    pub version: u32,
+    // How many ticks the attempt lasted
+    pub ticks: u32,
+    // Ticks on which the player changed direction, in order
+    pub toggles: Vec<u32>,
+}
+
+impl Replay {
+    pub fn new(level: &str) -> Replay {
+        Replay {
+            level: level.to_owned(),
+            version: REPLAY_VERSION,
+            ticks: 0,
+            toggles: Vec::new(),
+        }
+    }
+
+    // Call once for every tick the player survived, with the input that tick was stepped with
+    pub fn record(&mut self, input: Input) {
+        if input.toggle {
+            self.toggles.push(self.ticks);
+        }
+        self.ticks += 1;
+    }
+
+    // The input the player gave on `tick`
+    pub fn input(&self, tick: u32) -> Input {
+        Input {
+            toggle: self.toggles.binary_search(&tick).is_ok(),
+        }
+    }
+
+    // Plays the replay without a window to see how it really goes. The score is less than `ticks`
+    // if the player crashes (or finishes) before the replay is over
+    pub fn simulate(&self, level: &World) -> Outcome {
+        let mut sim = Simulation::new(level);
+        while sim.tick < self.ticks {
+            match sim.step(self.input(sim.tick)) {
+                Step::Alive | Step::Checkpoint => {}
+                Step::Died => break,
+                Step::Finished => return Outcome { score: sim.tick, finished: true },
+            }
+        }
+        Outcome { score: sim.tick, finished: false }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    // Ticks survived
+    pub score: u32,
+    // Reached the finish line, then the score is also how long that took
+    pub finished: bool,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::world::FinishLine;
+
+    // Plays the classic level turning every `every` ticks until the player crashes (or gives up)
+    fn play(every: u32) -> (Replay, u32) {
+        let world = World::classic();
+        let mut sim = Simulation::new(&world);
+        let mut replay = Replay::new("classic");
+        while sim.tick < 5000 {
+            let input = Input { toggle: sim.tick.is_multiple_of(every) };
+            if sim.step(input) == Step::Died {
+                break;
+            }
+            replay.record(input);
+        }
+        (replay, sim.tick)
+    }
+
+    #[test]
+    fn simulating_a_recording_gives_the_same_score() {
+        for every in [7, 13, 20, 50] {
+            let (replay, score) = play(every);
+            assert_eq!(replay.simulate(&World::classic()), Outcome { score, finished: false });
+        }
+    }
+
+    // Nothing in the way, only a finish line
+    pub(crate) fn open_level() -> World {
+        World {
+            objects: Vec::new(),
+            poly_objects: Vec::new(),
+            moving_objects: Vec::new(),
+            speed_increases: Vec::new(),
+            finish: Some(FinishLine { x: 700.0 }),
+            checkpoints: Vec::new(),
+            portals: Vec::new(),
+        }
+    }
+
+    // Flies straight to the finish line of `level`, recording it like the client does
+    pub(crate) fn finish(level: &World, name: &str) -> Replay {
+        let mut sim = Simulation::new(level);
+        let mut replay = Replay::new(name);
+        loop {
+            let step = sim.step(Input::default());
+            assert_ne!(step, Step::Died);
+            replay.record(Input::default());
+            if step == Step::Finished {
+                return replay;
+            }
+        }
+    }
+
+    #[test]
+    fn finishing_scores_the_ticks_it_took() {
+        let level = open_level();
+        let mut replay = finish(&level, "open");
+        // 3.5 pixels a tick until the front of the player is on the line
+        assert_eq!(replay.ticks, 191);
+        assert_eq!(replay.simulate(&level), Outcome { score: 191, finished: true });
+
+        // Whatever comes after the finish doesn't count
+        replay.ticks += 50;
+        assert_eq!(replay.simulate(&level), Outcome { score: 191, finished: true });
+        replay.ticks = 190;
+        assert_eq!(replay.simulate(&level), Outcome { score: 190, finished: false });
+    }
+
+    #[test]
+    fn tampered_replays_score_what_they_really_get() {
+        let (mut replay, score) = play(13);
+        // Claims to have lasted longer than it did
+        replay.ticks += 100;
+        assert_eq!(replay.simulate(&World::classic()).score, score);
+    }
+}
@@ -1,11 +1,27 @@
 use axum::{
-    extract::Json,
+    extract::{FromRef, Json, Path, Query},
+    http::{header, HeaderMap, StatusCode},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::{collections::HashSet, sync::{Arc, Mutex}};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    accounts::{self, Account, Accounts, Credentials, Registration},
+    assets::{self, Manifest},
+    community::{self, CommunityLevel, CommunityLevelInfo, Sort, Upload},
+    levels::{self, Level, LevelInfo, DEFAULT_LEVEL},
+    replay::{Replay, REPLAY_VERSION},
+    sim::TICK_RATE,
+    stats::{self, PlayerStats, Run},
+    storage::Storage,
+    world::World,
+};
 
 // use tokio::net::TcpListener;
 
@@ -13,6 +29,126 @@ use std::{collections::HashSet, sync::{Arc, Mutex}};
 pub struct Score {
     pub player: String,
     pub score: u32,
+    // Scores from before there were multiple levels were all on the default one
+    #[serde(default = "default_level")]
+    pub level: String,
+    // The account that set it, scores from before there were accounts don't have one
+    #[serde(default)]
+    pub player_id: Option<String>,
+    // Scores from before there were finish lines are all survival times
+    #[serde(default)]
+    pub metric: Metric,
+}
+
+fn default_level() -> String {
+    DEFAULT_LEVEL.to_owned()
+}
+
+// What a score measures, every level has a leaderboard for each. Survival is how many ticks the
+// player lasted, more is better. Completion is how many ticks it took to reach the finish line,
+// less is better
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    #[default]
+    Survival,
+    Completion,
+}
+
+impl Metric {
+    // What ?metric= calls it
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Survival => "survival",
+            Metric::Completion => "completion",
+        }
+    }
+
+    // Sorting by this puts the best scores first
+    fn sort_key(self, score: u32) -> i64 {
+        match self {
+            Metric::Survival => -(score as i64),
+            Metric::Completion => score as i64,
+        }
+    }
+}
+
+// What the client sends to /submit. The score only counts if the replay actually gets it.
+// `player` is ignored, the score goes to whoever the token belongs to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Submission {
+    pub player: String,
+    pub score: u32,
+    pub level: String,
+    pub replay: Replay,
+    // How many attempts and ticks it took to get there, for the stats. The replay is one of the
+    // attempts, so there's at least one and at least as much playtime as the replay is long
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub playtime: u32,
+}
+
+// Longer replays than this (an hour) are refused, so nobody can keep the server busy simulating
+const MAX_REPLAY_TICKS: u32 = TICK_RATE * 60 * 60;
+// Every tick checks the player against everything in the level, so levels with a lot in them get
+// fewer ticks. This is an hour on a level with 100 things in it
+const MAX_SIMULATION_WORK: u64 = MAX_REPLAY_TICKS as u64 * 100;
+
+// The longest replay the server simulates on this level
+fn max_replay_ticks(world: &World) -> u32 {
+    let per_tick = community::object_count(world) as u64 + 1;
+    (MAX_SIMULATION_WORK / per_tick).min(MAX_REPLAY_TICKS as u64) as u32
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+    pub level: Option<String>,
+    // Survival times unless it says otherwise
+    #[serde(default)]
+    pub metric: Metric,
+    // Skip this many entries, then return at most `limit` of them (everything if there's no limit)
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+// A score and where it places on its level. Players with the same score share a rank
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RankedScore {
+    pub rank: usize,
+    // Flattened, so clients that only know about `Score` can read it as one
+    #[serde(flatten)]
+    pub score: Score,
+}
+
+#[derive(Deserialize)]
+pub struct PlayerRankQuery {
+    pub level: Option<String>,
+    #[serde(default)]
+    pub metric: Metric,
+    // How many players above and below to include
+    pub around: Option<usize>,
+}
+
+// Where a player is on a level's leaderboard, along with the players around them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerRank {
+    pub rank: usize,
+    pub entries: Vec<RankedScore>,
+}
+
+const MAX_AROUND: usize = 50;
+
+#[derive(Deserialize)]
+pub struct BrowseQuery {
+    #[serde(default)]
+    pub sort: Sort,
+    // Only the levels of the player with this name
+    pub author: Option<String>,
+    // Only the levels with this in their title
+    pub search: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
 }
 
 // Shared state for storing scores
@@ -20,130 +156,666 @@ pub struct Score {
 #[derive(Clone)]
 pub struct Leaderboard {
     scores: Arc<Mutex<Vec<Score>>>,
+    runs: Arc<Mutex<Vec<Run>>>,
+    accounts: Arc<Mutex<Accounts>>,
+    storage: Arc<Storage>,
+}
+
+#[derive(Clone)]
+pub struct Levels {
+    levels: Arc<Vec<Level>>,
+    // The ones players uploaded
+    community: Arc<Mutex<Vec<CommunityLevel>>>,
+}
+
+impl Levels {
+    // The world of any level, whether it came with the server or a player made it
+    fn world(&self, id: &str) -> Option<World> {
+        if let Some(level) = self.levels.iter().find(|l| l.info.id == id) {
+            return Some(level.world.clone());
+        }
+        self.community.lock().unwrap().iter().find(|l| l.id == id).map(|l| l.world.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct Assets {
+    manifest: Arc<Manifest>,
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    leaderboard: Leaderboard,
+    levels: Levels,
+    assets: Assets,
+}
+
+impl FromRef<AppState> for Leaderboard {
+    fn from_ref(state: &AppState) -> Leaderboard {
+        state.leaderboard.clone()
+    }
+}
+
+impl FromRef<AppState> for Levels {
+    fn from_ref(state: &AppState) -> Levels {
+        state.levels.clone()
+    }
+}
+
+impl FromRef<AppState> for Assets {
+    fn from_ref(state: &AppState) -> Assets {
+        state.assets.clone()
+    }
 }
 
 pub fn router() -> Router {
+    router_with_storage(Storage::from_env())
+}
+
+pub fn router_with_storage(storage: Storage) -> Router {
+    // Renames are stored as the whole account again, so the last one of every id wins
+    let mut accounts = Accounts::default();
+    for account in storage.load::<Account>("accounts") {
+        accounts.insert(account);
+    }
+
+    // Every submission ever made is in the storage, so replaying them rebuilds the leaderboard.
+    // Sorted once at the end, not once per score
+    let mut scores = storage.load::<Score>("scores");
+    for score in &mut scores {
+        // Show the name the player has now, not the one they had back then
+        if let Some(account) = score.player_id.as_ref().and_then(|id| accounts.get(id)) {
+            score.player = account.name.clone();
+        }
+    }
+    sort_scores(&mut scores);
+
+    let runs = storage.load::<Run>("runs");
+    let community = storage.load::<CommunityLevel>("community_levels");
+
     let leaderboard = Leaderboard {
-        scores: Arc::new(Mutex::new(Vec::new())),
+        scores: Arc::new(Mutex::new(scores)),
+        runs: Arc::new(Mutex::new(runs)),
+        accounts: Arc::new(Mutex::new(accounts)),
+        storage: Arc::new(storage),
+    };
+
+    let mut levels = levels::load_levels();
+    // Without the default level /world would have nothing to serve
+    if !levels.iter().any(|l| l.info.id == DEFAULT_LEVEL) {
+        levels.insert(0, Level {
+            info: LevelInfo {
+                id: DEFAULT_LEVEL.to_owned(),
+                name: "Classic".to_owned(),
+                difficulty: 1,
+            },
+            world: World::classic(),
+        });
+    }
+    let levels = Levels {
+        levels: Arc::new(levels),
+        community: Arc::new(Mutex::new(community)),
+    };
+
+    let assets = Assets {
+        manifest: Arc::new(assets::load_manifest()),
     };
 
     Router::new()
         .route("/leaderboard", get(get_leaderboard))
+        .route("/leaderboard/player/{name}", get(get_player_rank))
+        .route("/register", post(register))
+        .route("/rename", post(rename))
         .route("/submit", post(submit_score))
         .route("/world", get(world))
-        .with_state(leaderboard)
+        .route("/stats/{player}", get(get_stats))
+        .route("/levels", get(get_levels).post(upload_level))
+        .route("/levels/community", get(browse_levels))
+        .route("/levels/{id}", get(get_level))
+        .route("/assets/manifest", get(get_manifest))
+        .with_state(AppState { leaderboard, levels, assets })
 }
 
-// Submit a score
-async fn submit_score(
-    axum::extract::State(state): axum::extract::State<Leaderboard>,
-    Json(new_score): Json<Score>,
-) -> &'static str {
-    let mut scores = state.scores.lock().unwrap();
+fn insert_score(scores: &mut Vec<Score>, new_score: Score) {
     scores.push(new_score);
-    scores.sort_by(|a, b| b.score.cmp(&a.score));
+    sort_scores(scores);
+}
+
+// Best first, and only the best of every player on every leaderboard. The sort is stable, so of two
+// equal scores the older one stays
+fn sort_scores(scores: &mut Vec<Score>) {
+    scores.sort_by_key(|s| (s.metric, s.metric.sort_key(s.score)));
     // scores.dedup_by(|a, b| a.player == b.player); // dedup_by only removes consecutive
-    // duplicates, so we need to do it manually
+    // duplicates, so we need to do it manually. Only the best per player *per level* (and metric)
+    // is kept. Players are told apart by their account, or by their name if the score is older
+    // than that
     let mut seen = HashSet::new();
-    scores.retain(|e| seen.insert(e.player.clone()));
-    println!("{:?} {:?}", scores, seen);
-    "Score submitted!1!!"
+    scores.retain(|e| seen.insert((e.player_id.clone().unwrap_or(e.player.clone()), e.level.clone(), e.metric)));
+}
+
+// The account whose token is in the Authorization header
+fn authenticate(headers: &HeaderMap, accounts: &Accounts) -> Result<Account, (StatusCode, &'static str)> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "Log in first, register at /register"))?;
+    accounts
+        .by_token(token.trim())
+        .cloned()
+        .ok_or((StatusCode::UNAUTHORIZED, "There is no account with that token"))
+}
+
+fn check_name(name: &str) -> Result<(), (StatusCode, &'static str)> {
+    accounts::check_name(name).map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+// Make an account. The token in the response is the only way to ever use it, so keep it safe
+async fn register(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    Json(registration): Json<Registration>,
+) -> Result<Json<Credentials>, (StatusCode, &'static str)> {
+    let name = registration.name.trim();
+    check_name(name)?;
+    let mut accounts = state.accounts.lock().unwrap();
+    if accounts.name_taken(name) {
+        return Err((StatusCode::CONFLICT, "Someone already has that name"));
+    }
+    let (account, credentials) = accounts::new_account(name);
+    if let Err(e) = state.storage.append("accounts", &account) {
+        eprintln!("Failed to store account {} ({})", account.id, e);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the account"));
+    }
+    accounts.insert(account);
+    Ok(Json(credentials))
+}
+
+// Change the name of the account, its scores come along
+async fn rename(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    headers: HeaderMap,
+    Json(registration): Json<Registration>,
+) -> Result<&'static str, (StatusCode, &'static str)> {
+    let name = registration.name.trim();
+    check_name(name)?;
+    let mut accounts = state.accounts.lock().unwrap();
+    let mut account = authenticate(&headers, &accounts)?;
+    if accounts.name_taken(name) && !account.name.eq_ignore_ascii_case(name) {
+        return Err((StatusCode::CONFLICT, "Someone already has that name"));
+    }
+    account.name = name.to_owned();
+    if let Err(e) = state.storage.append("accounts", &account) {
+        eprintln!("Failed to store account {} ({})", account.id, e);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the account"));
+    }
+    for score in state.scores.lock().unwrap().iter_mut() {
+        if score.player_id.as_ref() == Some(&account.id) {
+            score.player = account.name.clone();
+        }
+    }
+    accounts.insert(account);
+    Ok("Renamed!")
 }
 
-// Get the leaderboard
+// Submit a score. The replay is played on the server and only the score it gets is recorded
+async fn submit_score(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    axum::extract::State(levels): axum::extract::State<Levels>,
+    headers: HeaderMap,
+    Json(submission): Json<Submission>,
+) -> Result<&'static str, (StatusCode, &'static str)> {
+    let account = authenticate(&headers, &state.accounts.lock().unwrap())?;
+    let Some(world) = levels.world(&submission.level) else {
+        return Err((StatusCode::NOT_FOUND, "There is no such level"));
+    };
+    let replay = &submission.replay;
+    if replay.level != submission.level {
+        return Err((StatusCode::BAD_REQUEST, "The replay is of a different level"));
+    }
+    if replay.version != REPLAY_VERSION {
+        return Err((StatusCode::BAD_REQUEST, "The replay is from a different version of the game"));
+    }
+    if replay.ticks > max_replay_ticks(&world) {
+        return Err((StatusCode::BAD_REQUEST, "The replay is too long"));
+    }
+    if !replay.toggles.windows(2).all(|w| w[0] < w[1]) {
+        return Err((StatusCode::BAD_REQUEST, "The replay is broken"));
+    }
+    if submission.attempts == 0 {
+        return Err((StatusCode::BAD_REQUEST, "There has to be at least one attempt"));
+    }
+    // None of the attempts can be longer than a replay is allowed to be
+    let max_playtime = submission.attempts as u64 * MAX_REPLAY_TICKS as u64;
+    if submission.playtime < replay.ticks || submission.playtime as u64 > max_playtime {
+        return Err((StatusCode::BAD_REQUEST, "The playtime doesn't add up"));
+    }
+    // Takes a while, so it doesn't hold up everything else on this thread
+    let simulated = replay.clone();
+    let outcome = tokio::task::spawn_blocking(move || simulated.simulate(&world))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check the replay"))?;
+    let score = outcome.score;
+    if score != submission.score {
+        println!("Rejected {:?} on {}, the replay scores {}", account.name, submission.level, score);
+        return Err((StatusCode::BAD_REQUEST, "The score doesn't match the replay"));
+    }
+
+    let run = Run {
+        player_id: account.id.clone(),
+        level: submission.level.clone(),
+        score,
+        attempts: submission.attempts,
+        playtime: submission.playtime,
+        time: now(),
+        finished: outcome.finished,
+    };
+    // Losing a run only makes the stats a bit off, so the score still counts if this fails
+    if let Err(e) = state.storage.append("runs", &run) {
+        eprintln!("Failed to store run {:?} ({})", run, e);
+    }
+    state.runs.lock().unwrap().push(run);
+
+    let survival = Score {
+        player: account.name,
+        score,
+        level: submission.level,
+        player_id: Some(account.id),
+        metric: Metric::Survival,
+    };
+    // Reaching the finish line also puts the time it took on the completion leaderboard
+    let completion = outcome.finished.then(|| Score { metric: Metric::Completion, ..survival.clone() });
+    let mut scores = state.scores.lock().unwrap();
+    for new_score in std::iter::once(survival).chain(completion) {
+        // Write it down first, a score that only lives in memory is lost on the next restart
+        if let Err(e) = state.storage.append("scores", &new_score) {
+            eprintln!("Failed to store score {:?} ({})", new_score, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the score"));
+        }
+        insert_score(&mut scores, new_score);
+    }
+    Ok("Score submitted!1!!")
+}
+
+// Seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Ranks every score on its own level's leaderboard. `scores` is sorted best first, so that's just
+// counting
+fn ranked(scores: &[Score]) -> Vec<RankedScore> {
+    // Per leaderboard: how many scores came before, and the rank of the last one
+    let mut levels: HashMap<(&str, Metric), (usize, u32, usize)> = HashMap::new();
+    scores
+        .iter()
+        .map(|score| {
+            let (count, last_score, last_rank) = levels.entry((&score.level, score.metric)).or_insert((0, u32::MAX, 0));
+            *count += 1;
+            if score.score != *last_score {
+                *last_score = score.score;
+                *last_rank = *count;
+            }
+            RankedScore { rank: *last_rank, score: score.clone() }
+        })
+        .collect()
+}
+
+// Get the leaderboard, of a single level if ?level= is given, a page of it with ?offset=&limit=.
+// It has survival times, or the times it took to finish with ?metric=completion
 async fn get_leaderboard(
     axum::extract::State(state): axum::extract::State<Leaderboard>,
-) -> Json<Vec<Score>> {
+    Query(query): Query<LeaderboardQuery>,
+) -> Json<Vec<RankedScore>> {
     let scores = state.scores.lock().unwrap();
-    Json(scores.clone())
+    Json(
+        ranked(&scores)
+            .into_iter()
+            .filter(|s| s.score.metric == query.metric)
+            .filter(|s| query.level.as_ref().is_none_or(|level| &s.score.level == level))
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect(),
+    )
 }
 
-#[derive(Serialize, Deserialize)]
-struct World {
-    objects: Vec<serde_json::Value>,
-    poly_objects: Vec<serde_json::Value>,
-    moving_objects: Vec<serde_json::Value>,
-    speed_increases: Vec<serde_json::Value>,
+// A player's rank on a level (the default one without ?level=) and the ?around= (5) players above
+// and below them. ?metric= works like it does for /leaderboard
+async fn get_player_rank(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    Path(name): Path<String>,
+    Query(query): Query<PlayerRankQuery>,
+) -> Result<Json<PlayerRank>, (StatusCode, &'static str)> {
+    let level = query.level.unwrap_or(default_level());
+    let around = query.around.unwrap_or(5).min(MAX_AROUND);
+    let scores = state.scores.lock().unwrap();
+    let entries: Vec<RankedScore> = ranked(&scores)
+        .into_iter()
+        .filter(|s| s.score.level == level && s.score.metric == query.metric)
+        .collect();
+    // Names are case insensitive, like accounts
+    let Some(position) = entries.iter().position(|s| s.score.player.eq_ignore_ascii_case(&name)) else {
+        return Err((StatusCode::NOT_FOUND, "That player has no score on this level"));
+    };
+    Ok(Json(PlayerRank {
+        rank: entries[position].rank,
+        entries: entries[position.saturating_sub(around)..(position + around + 1).min(entries.len())].to_vec(),
+    }))
 }
 
-impl ToString for World {
-    fn to_string(&self) -> String {
-        json!({
-            "objects": self.objects,
-            "poly_objects": self.poly_objects,
-            "moving_objects": self.moving_objects,
-            "speed_increases": self.speed_increases,
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    pub level: Option<String>,
+}
+
+// Everything about how a player has been doing on a level (the default one without ?level=)
+async fn get_stats(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    Path(player): Path<String>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<PlayerStats>, (StatusCode, &'static str)> {
+    let level = query.level.unwrap_or(default_level());
+    let Some(account) = state.accounts.lock().unwrap().by_name(&player).cloned() else {
+        return Err((StatusCode::NOT_FOUND, "There is no player with that name"));
+    };
+    // Only the player's runs, so everyone else can submit while the stats are worked out
+    let runs: Vec<Run> = state.runs.lock().unwrap()
+        .iter()
+        .filter(|r| r.player_id == account.id && r.level == level)
+        .cloned()
+        .collect();
+    match stats::player_stats(&runs, &account.id, &account.name, &level) {
+        Some(stats) => Ok(Json(stats)),
+        None => Err((StatusCode::NOT_FOUND, "That player hasn't played this level")),
+    }
+}
+
+// The default level, for clients from before there were multiple levels.
+// This used to be the world JSON inside a JSON string. Those old clients strip the quotes and
+// backslashes off before parsing, which does nothing to the plain object, so they still work
+async fn world(
+    axum::extract::State(state): axum::extract::State<Levels>,
+) -> Json<World> {
+    let level = state.levels.iter().find(|l| l.info.id == DEFAULT_LEVEL).unwrap();
+    Json(level.world.clone())
+}
+
+// List every level
+async fn get_levels(
+    axum::extract::State(state): axum::extract::State<Levels>,
+) -> Json<Vec<LevelInfo>> {
+    Json(state.levels.iter().map(|l| l.info.clone()).collect())
+}
+
+// Get the world of a single level, community levels included
+async fn get_level(
+    axum::extract::State(state): axum::extract::State<Levels>,
+    Path(id): Path<String>,
+) -> Result<Json<World>, StatusCode> {
+    match state.world(&id) {
+        Some(world) => Ok(Json(world)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Publish a level. It goes through the same checks as the levels the server comes with
+async fn upload_level(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    axum::extract::State(levels): axum::extract::State<Levels>,
+    headers: HeaderMap,
+    Json(upload): Json<Upload>,
+) -> Result<Json<CommunityLevelInfo>, (StatusCode, String)> {
+    let account = authenticate(&headers, &state.accounts.lock().unwrap()).map_err(|(code, e)| (code, e.to_owned()))?;
+    let title = upload.title.trim();
+    if title.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The title is empty".to_owned()));
+    }
+    if title.chars().count() > community::MAX_TITLE_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "The title is too long".to_owned()));
+    }
+    // Says what's wrong with it, so the author can fix it
+    let world = World::from_value(upload.world).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    if community::object_count(&world) > community::MAX_OBJECTS {
+        return Err((StatusCode::BAD_REQUEST, "The level is too big".to_owned()));
+    }
+
+    let mut community = levels.community.lock().unwrap();
+    let id = loop {
+        let id = format!("community-{:08x}", rand::random::<u32>());
+        if !community.iter().any(|l| l.id == id) {
+            break id;
+        }
+    };
+    let level = CommunityLevel {
+        id,
+        title: title.to_owned(),
+        author_id: account.id,
+        difficulty: upload.difficulty.clamp(1, 5),
+        created: now(),
+        world,
+    };
+    if let Err(e) = state.storage.append("community_levels", &level) {
+        eprintln!("Failed to store level {} ({})", level.id, e);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the level".to_owned()));
+    }
+    println!("{} uploaded {} ({})", account.name, level.title, level.id);
+    let info = CommunityLevelInfo {
+        id: level.id.clone(),
+        title: level.title.clone(),
+        author: account.name,
+        difficulty: level.difficulty,
+        created: level.created,
+        plays: 0,
+    };
+    community.push(level);
+    Ok(Json(info))
+}
+
+// Community levels, newest first (or ?sort=most_played). ?author= only has that player's,
+// ?search= only the ones with it in the title, and ?offset=&limit= pages through them
+async fn browse_levels(
+    axum::extract::State(state): axum::extract::State<Leaderboard>,
+    axum::extract::State(levels): axum::extract::State<Levels>,
+    Query(query): Query<BrowseQuery>,
+) -> Json<Vec<CommunityLevelInfo>> {
+    let accounts = state.accounts.lock().unwrap();
+    let author_id = match &query.author {
+        Some(name) => match accounts.by_name(name) {
+            Some(account) => Some(account.id.clone()),
+            None => return Json(Vec::new()),
+        },
+        None => None,
+    };
+
+    let mut plays: HashMap<&str, usize> = HashMap::new();
+    let runs = state.runs.lock().unwrap();
+    for run in runs.iter() {
+        *plays.entry(&run.level).or_default() += 1;
+    }
+
+    let mut found: Vec<CommunityLevelInfo> = levels
+        .community
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|l| author_id.as_ref().is_none_or(|id| &l.author_id == id))
+        .map(|l| CommunityLevelInfo {
+            id: l.id.clone(),
+            title: l.title.clone(),
+            author: accounts.get(&l.author_id).map(|a| a.name.clone()).unwrap_or_default(),
+            difficulty: l.difficulty,
+            created: l.created,
+            plays: plays.get(l.id.as_str()).copied().unwrap_or(0),
         })
-        .to_string()
-    }
-}
-
-async fn world() -> Json<String> {
-    let world = World {
-        objects: vec![
-            json!({
-                "x": 0.0,
-                "y": 250.0,
-                "width": 5000.0,
-                "height": 50.0,
-            }),
-            json!({
-                "x": 0.0,
-                "y": -300.0,
-                "width": 5000.0,
-                "height": 50.0,
-            }),
-            json!({
-                "x": 500.0,
-                "y": 15.0,
-                "width": 50.0,
-                "height": 235.0,
-            }),
-            json!({
-                "x": 625.0,
-                "y": -250.0,
-                "width": 50.0,
-                "height": 235.0,
-            }),
-        ],
-        poly_objects: vec![json!({
-            "points": [
-                {"x": 775.0, "y": -80.0},
-                {"x": 775.0, "y": 250.0},
-                {"x": 1075.0, "y": 250.0},
-                {"x": 1075.0, "y": 0.0},
-                {"x": 975.0, "y": -80.0},
-            ],
-        })],
-        moving_objects: vec![
-            json!({
-                "from": {"x": 1100.0, "y": -225.0},
-                "to": {"x": 1200.0, "y": 150.0},
-                "width": 50.0,
-                "height": 100.0,
-                "speed": 3.0,
-            }),
-            json!({
-                "from": {"x": 1300.0, "y": 150.0},
-                "to": {"x": 1400.0, "y": -225.0},
-                "width": 50.0,
-                "height": 100.0,
-                "speed": 3.0,
-            }),
-            json!({
-                "from": {"x": 1500.0, "y": 0.0},
-                "to": {"x": 1600.0, "y": 0.0},
-                "width": 100.0,
-                "height":
-                50.0,
-                "speed": 3.0,
-            }),
-        ],
-        speed_increases: vec![json!({
-            "x": 1075.0,
-            "y": -200.0,
-            "speed_change": 2.0,
-        })],
+        .filter(|l| query.search.as_ref().is_none_or(|search| community::matches(l, search)))
+        .collect();
+    community::sort(&mut found, query.sort);
+    Json(found.into_iter().skip(query.offset.unwrap_or(0)).take(query.limit.unwrap_or(usize::MAX)).collect())
+}
+
+// The hashes of the current assets, so clients only download the ones that changed
+async fn get_manifest(
+    axum::extract::State(state): axum::extract::State<Assets>,
+) -> Json<Manifest> {
+    Json((*state.manifest).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        replay::tests::{finish, open_level},
+        sim::{Input, Simulation, Step},
+        storage::tests::temp_dir,
     };
-    Json(world.to_string())
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    async fn request(app: &Router, method: &str, uri: &str, token: Option<&str>, body: Option<String>) -> (StatusCode, String) {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = match body {
+            Some(body) => builder.header(header::CONTENT_TYPE, "application/json").body(Body::from(body)),
+            None => builder.body(Body::empty()),
+        };
+        let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    // Never turns, so it flies into the ceiling
+    fn crash(level: &str, world: &World) -> Replay {
+        let mut sim = Simulation::new(world);
+        let mut replay = Replay::new(level);
+        while sim.step(Input::default()) != Step::Died {
+            replay.record(Input::default());
+        }
+        replay
+    }
+
+    // A registered player's token, and the default level
+    async fn setup(app: &Router) -> (String, World) {
+        let (status, body) = request(app, "POST", "/register", None, Some(r#"{"name": "tester"}"#.to_owned())).await;
+        assert_eq!(status, StatusCode::OK);
+        let credentials: Credentials = serde_json::from_str(&body).unwrap();
+        let (_, body) = request(app, "GET", &format!("/levels/{}", DEFAULT_LEVEL), None, None).await;
+        (credentials.token, serde_json::from_str(&body).unwrap())
+    }
+
+    fn submission(replay: Replay, score: u32) -> String {
+        serde_json::to_string(&Submission {
+            player: "tester".to_owned(),
+            score,
+            level: DEFAULT_LEVEL.to_owned(),
+            attempts: 1,
+            playtime: replay.ticks,
+            replay,
+        }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_a_tampered_score() {
+        let app = router_with_storage(Storage::Memory);
+        let (token, world) = setup(&app).await;
+        let replay = crash(DEFAULT_LEVEL, &world);
+        let score = replay.ticks + 1;
+        let (status, _) = request(&app, "POST", "/submit", Some(&token), Some(submission(replay, score))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (_, body) = request(&app, "GET", "/leaderboard", None, None).await;
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn rejects_made_up_playtime() {
+        let app = router_with_storage(Storage::Memory);
+        let (token, world) = setup(&app).await;
+        let replay = crash(DEFAULT_LEVEL, &world);
+        let ticks = replay.ticks;
+        for (attempts, playtime) in [(0, ticks), (1, ticks - 1), (1, u32::MAX), (2, MAX_REPLAY_TICKS * 2 + 1)] {
+            let mut submission: Submission = serde_json::from_str(&submission(replay.clone(), ticks)).unwrap();
+            submission.attempts = attempts;
+            submission.playtime = playtime;
+            let (status, _) = request(&app, "POST", "/submit", Some(&token), Some(serde_json::to_string(&submission).unwrap())).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} attempts, {} playtime", attempts, playtime);
+        }
+    }
+
+    #[tokio::test]
+    async fn scores_survive_a_restart() {
+        let dir = temp_dir("router");
+        let app = router_with_storage(Storage::JsonLines(dir.clone()));
+        let (token, world) = setup(&app).await;
+        let replay = crash(DEFAULT_LEVEL, &world);
+        let score = replay.ticks;
+        let (status, body) = request(&app, "POST", "/submit", Some(&token), Some(submission(replay, score))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let restarted = router_with_storage(Storage::JsonLines(dir.clone()));
+        let (status, body) = request(&restarted, "GET", "/leaderboard", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let scores: Vec<RankedScore> = serde_json::from_str(&body).unwrap();
+        assert_eq!(scores.len(), 1);
+        assert_eq!(scores[0].score.player, "tester");
+        assert_eq!(scores[0].score.score, score);
+        assert_eq!(scores[0].rank, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_finished_runs_go_on_the_completion_leaderboard() {
+        let app = router_with_storage(Storage::Memory);
+        let (token, _) = setup(&app).await;
+        let world = open_level();
+        let upload = Upload { title: "Open".to_owned(), difficulty: 1, world: serde_json::to_value(&world).unwrap() };
+        let (status, body) = request(&app, "POST", "/levels", Some(&token), Some(serde_json::to_string(&upload).unwrap())).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let level: CommunityLevelInfo = serde_json::from_str(&body).unwrap();
+
+        let submit = |replay: Replay| {
+            let submission = Submission {
+                player: "tester".to_owned(),
+                score: replay.ticks,
+                level: level.id.clone(),
+                attempts: 1,
+                playtime: replay.ticks,
+                replay,
+            };
+            let (app, token) = (app.clone(), token.clone());
+            async move { request(&app, "POST", "/submit", Some(&token), Some(serde_json::to_string(&submission).unwrap())).await }
+        };
+        let leaderboard = |metric: Metric| {
+            let (app, uri) = (app.clone(), format!("/leaderboard?level={}&metric={}", level.id, metric.name()));
+            async move { serde_json::from_str::<Vec<RankedScore>>(&request(&app, "GET", &uri, None, None).await.1).unwrap() }
+        };
+
+        let finished = finish(&world, &level.id);
+        let mut unfinished = finished.clone();
+        unfinished.ticks -= 1;
+        assert_eq!(submit(unfinished).await.0, StatusCode::OK);
+        assert!(leaderboard(Metric::Completion).await.is_empty());
+        assert_eq!(leaderboard(Metric::Survival).await.len(), 1);
+
+        let ticks = finished.ticks;
+        assert_eq!(submit(finished).await.0, StatusCode::OK);
+        let completion = leaderboard(Metric::Completion).await;
+        assert_eq!(completion.len(), 1);
+        assert_eq!((completion[0].score.score, completion[0].rank), (ticks, 1));
+    }
+
+    #[tokio::test]
+    async fn player_rank_ignores_case() {
+        let app = router_with_storage(Storage::Memory);
+        let (token, world) = setup(&app).await;
+        let replay = crash(DEFAULT_LEVEL, &world);
+        let score = replay.ticks;
+        request(&app, "POST", "/submit", Some(&token), Some(submission(replay, score))).await;
+
+        let (status, body) = request(&app, "GET", "/leaderboard/player/TeStEr", None, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let rank: PlayerRank = serde_json::from_str(&body).unwrap();
+        assert_eq!((rank.rank, rank.entries.len()), (1, 1));
+    }
 }
@@ -1,11 +1,11 @@
-mod router;
+use hardest_game_ever::router;
 
 #[tokio::main]
 async fn main() {
     let app = router::router();
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     println!("Runnin on {} rn", listener.local_addr().unwrap());
-    let _ = axum::serve(listener, app)
+    axum::serve(listener, app)
         .await
         .unwrap();
 }
@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::{env, fs, io, path::Path, sync::OnceLock};
+
+// The client's settings. They live in settings.json in the game's data directory, env vars
+// override the file and command line flags override both. Overrides are never saved to the file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    // Where the leaderboard and levels come from
+    pub server_url: String,
+    // Where the images are downloaded from, "<asset_url>/<name>.png"
+    pub asset_url: String,
+    pub window_width: u32,
+    pub window_height: u32,
+    pub fullscreen: bool,
+    // 0 to 1. Nothing uses it yet, there's no sound
+    pub volume: f32,
+    pub keys: KeyBindings,
+}
+
+// Names of keys, like "Space" or "Q"
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub jump: String,
+    // Leave the level (or replay) you're in
+    pub leave: String,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            // Debug builds talk to a server running on this computer
+            server_url: if cfg!(debug_assertions) {
+                "http://127.0.0.1:3000".to_owned()
+            } else {
+                "https://hardest-game-ever-d2ht.shuttle.app".to_owned()
+            },
+            asset_url: "https://muhtasim-rasheed.github.io/hardest-game-ever/assets".to_owned(),
+            window_width: 1600,
+            window_height: 900,
+            fullscreen: false,
+            volume: 1.0,
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            jump: "Space".to_owned(),
+            leave: "Escape".to_owned(),
+        }
+    }
+}
+
+const USAGE: &str = "Usage: client [--server-url URL] [--asset-url URL] [--width PIXELS] [--height PIXELS] [--fullscreen | --windowed] [--volume 0-1]";
+
+impl Settings {
+    fn file(directory: &str) -> String {
+        format!("{}/settings.json", directory)
+    }
+
+    // What's in the settings file, or the defaults if there isn't one (yet)
+    pub fn load(directory: &str) -> Settings {
+        let file = Settings::file(directory);
+        if !Path::new(&file).exists() {
+            return Settings::default();
+        }
+        match fs::read_to_string(&file).map_err(|e| e.to_string()).and_then(|data| {
+            serde_json::from_str(&data).map_err(|e| e.to_string())
+        }) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Couldn't read {}, using the default settings ({})", file, e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save(&self, directory: &str) -> io::Result<()> {
+        fs::create_dir_all(directory)?;
+        fs::write(Settings::file(directory), serde_json::to_string_pretty(self).unwrap())
+    }
+
+    // The settings the game actually runs with: the file, then env vars, then command line flags
+    pub fn with_overrides(self) -> Settings {
+        self.with(Overrides::get())
+    }
+
+    pub fn with(mut self, overrides: &Overrides) -> Settings {
+        let overrides = overrides.clone();
+        self.server_url = overrides.server_url.unwrap_or(self.server_url);
+        self.asset_url = overrides.asset_url.unwrap_or(self.asset_url);
+        self.window_width = overrides.window_width.unwrap_or(self.window_width);
+        self.window_height = overrides.window_height.unwrap_or(self.window_height);
+        self.fullscreen = overrides.fullscreen.unwrap_or(self.fullscreen);
+        self.volume = overrides.volume.unwrap_or(self.volume);
+        self
+    }
+}
+
+// What the env vars and command line flags change. They can't change while the game runs, so
+// they're only read (and complained about) once
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overrides {
+    pub server_url: Option<String>,
+    pub asset_url: Option<String>,
+    pub window_width: Option<u32>,
+    pub window_height: Option<u32>,
+    pub fullscreen: Option<bool>,
+    pub volume: Option<f32>,
+}
+
+static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+
+impl Overrides {
+    pub fn get() -> &'static Overrides {
+        OVERRIDES.get_or_init(|| {
+            let (overrides, error) = Overrides::read(|name| env::var(name).ok(), env::args().skip(1));
+            if let Err(e) = error {
+                eprintln!("{}\n{}", e, USAGE);
+            }
+            overrides
+        })
+    }
+
+    // `var` looks up env vars like in data_dir::resolve. Flags after a bad one are ignored, the
+    // ones before it still count
+    pub fn read(var: impl Fn(&str) -> Option<String>, args: impl Iterator<Item = String>) -> (Overrides, Result<(), String>) {
+        let mut overrides = Overrides::default();
+        overrides.apply_env(var);
+        let result = overrides.apply_args(args);
+        (overrides, result)
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(url) = var("SERVER_URL") {
+            self.server_url = Some(url);
+        }
+        if let Some(url) = var("ASSET_URL") {
+            self.asset_url = Some(url);
+        }
+        if let Some(width) = var("WINDOW_WIDTH").and_then(|w| w.parse().ok()) {
+            self.window_width = Some(width);
+        }
+        if let Some(height) = var("WINDOW_HEIGHT").and_then(|h| h.parse().ok()) {
+            self.window_height = Some(height);
+        }
+        if let Some(fullscreen) = var("FULLSCREEN") {
+            self.fullscreen = Some(matches!(fullscreen.as_str(), "1" | "true" | "yes"));
+        }
+        if let Some(volume) = var("VOLUME").and_then(|v| v.parse::<f32>().ok()) {
+            self.volume = Some(volume.clamp(0.0, 1.0));
+        }
+    }
+
+    fn apply_args(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--fullscreen" => self.fullscreen = Some(true),
+                "--windowed" => self.fullscreen = Some(false),
+                "--server-url" | "--asset-url" | "--width" | "--height" | "--volume" => {
+                    let value = args.next().ok_or(format!("{} needs a value", arg))?;
+                    let number = || value.parse::<u32>().map_err(|_| format!("{} should be a whole number", arg));
+                    match arg.as_str() {
+                        "--server-url" => self.server_url = Some(value.clone()),
+                        "--asset-url" => self.asset_url = Some(value.clone()),
+                        "--width" => self.window_width = Some(number()?),
+                        "--height" => self.window_height = Some(number()?),
+                        _ => {
+                            let volume: f32 = value.parse().map_err(|_| "--volume should be a number".to_owned())?;
+                            self.volume = Some(volume.clamp(0.0, 1.0));
+                        }
+                    }
+                }
+                _ => return Err(format!("Unknown flag {}", arg)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(vars: &[(&str, &str)], args: &[&str]) -> (Overrides, Result<(), String>) {
+        let var = |name: &str| vars.iter().find(|(n, _)| *n == name).map(|(_, value)| value.to_string());
+        Overrides::read(var, args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn from_file() -> Settings {
+        Settings { server_url: "http://file".to_owned(), window_width: 800, volume: 0.5, ..Settings::default() }
+    }
+
+    #[test]
+    fn the_file_counts_without_overrides() {
+        let (overrides, result) = read(&[], &[]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(from_file().with(&overrides), from_file());
+    }
+
+    #[test]
+    fn env_vars_beat_the_file_and_flags_beat_both() {
+        let vars = [("SERVER_URL", "http://env"), ("WINDOW_WIDTH", "1024"), ("FULLSCREEN", "yes")];
+        let (overrides, _) = read(&vars, &[]);
+        let settings = from_file().with(&overrides);
+        assert_eq!((settings.server_url.as_str(), settings.window_width, settings.fullscreen), ("http://env", 1024, true));
+        assert_eq!(settings.volume, 0.5);
+
+        let (overrides, _) = read(&vars, &["--server-url", "http://flag", "--windowed"]);
+        let settings = from_file().with(&overrides);
+        assert_eq!((settings.server_url.as_str(), settings.window_width, settings.fullscreen), ("http://flag", 1024, false));
+    }
+
+    #[test]
+    fn env_vars_that_dont_parse_are_ignored() {
+        let (overrides, _) = read(&[("WINDOW_WIDTH", "wide"), ("VOLUME", "loud")], &[]);
+        assert_eq!(overrides, Overrides::default());
+    }
+
+    #[test]
+    fn flags() {
+        let args = ["--asset-url", "http://assets", "--width", "640", "--height", "480", "--fullscreen", "--volume", "2"];
+        let (overrides, result) = read(&[], &args);
+        assert_eq!(result, Ok(()));
+        assert_eq!(overrides, Overrides {
+            server_url: None,
+            asset_url: Some("http://assets".to_owned()),
+            window_width: Some(640),
+            window_height: Some(480),
+            fullscreen: Some(true),
+            volume: Some(1.0),
+        });
+    }
+
+    #[test]
+    fn bad_flags_stop_the_rest() {
+        let (overrides, result) = read(&[], &["--width", "640", "--height", "tall", "--fullscreen"]);
+        assert_eq!(result, Err("--height should be a whole number".to_owned()));
+        assert_eq!(overrides, Overrides { window_width: Some(640), ..Overrides::default() });
+
+        assert_eq!(read(&[], &["--server-url"]).1, Err("--server-url needs a value".to_owned()));
+        assert_eq!(read(&[], &["--loud"]).1, Err("Unknown flag --loud".to_owned()));
+    }
+}
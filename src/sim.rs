@@ -0,0 +1,618 @@
+use macroquad::math::{vec2, Vec2};
+
+use crate::{geometry::line_intersect, world};
+
+// The game itself, without any drawing or input handling so it can run without a window.
+// The client feeds it an `Input` every tick and draws whatever state it ends up in.
+
+pub const PLAYER_SPEED: f32 = 1.0;
+
+// How many times per second the game is stepped, no matter how fast the screen refreshes.
+// Scores are counted in ticks of this rate
+pub const TICK_RATE: u32 = 60;
+
+pub fn ticks_to_seconds(ticks: u32) -> f32 {
+    ticks as f32 / TICK_RATE as f32
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hitbox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Hitbox {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Hitbox {
+        Hitbox { x, y, width, height }
+    }
+
+    pub fn collides(&self, other: &Hitbox) -> bool {
+        self.x < other.x + other.width &&
+        self.x + self.width > other.x &&
+        self.y < other.y + other.height &&
+        self.y + self.height > other.y
+    }
+
+    pub fn get_pos(&self) -> Vec2 {
+        vec2(self.x, self.y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolygonHitbox {
+    pub points: Vec<Vec2>,
+}
+
+impl PolygonHitbox {
+    pub fn new(points: Vec<Vec2>) -> PolygonHitbox {
+        PolygonHitbox { points }
+    }
+
+    pub fn collides(&self, other: &PolygonHitbox) -> bool {
+        for i in 0..self.points.len() {
+            let p1 = self.points[i];
+            let p2 = self.points[(i + 1) % self.points.len()];
+
+            for j in 0..other.points.len() {
+                let q1 = other.points[j];
+                let q2 = other.points[(j + 1) % other.points.len()];
+
+                if line_intersect(p1, p2, q1, q2) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovingObject {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub forward: bool,
+    pub speed: f32,
+    pub hitbox: Hitbox,
+}
+
+impl MovingObject {
+    pub fn new(from: Vec2, to: Vec2, width: f32, height: f32, speed: f32) -> MovingObject {
+        MovingObject {
+            from,
+            to,
+            forward: true,
+            speed,
+            hitbox: Hitbox::new(from.x, from.y, width, height),
+        }
+    }
+
+    pub fn update(&mut self) {
+        let direction = self.to - self.from;
+        let distance = direction.length();
+        if distance == 0.0 {
+            return; // Nowhere to go, and dividing by it would make everything NaN
+        }
+        let normalized_direction = direction / distance;
+
+        if self.forward {
+            self.hitbox.x += normalized_direction.x * self.speed;
+            self.hitbox.y += normalized_direction.y * self.speed;
+        } else {
+            self.hitbox.x -= normalized_direction.x * self.speed;
+            self.hitbox.y -= normalized_direction.y * self.speed;
+        }
+
+        let current_distance = (self.hitbox.get_pos() - self.from).length();
+
+        if current_distance >= distance {
+            self.forward = false;
+        } else if current_distance <= 0.0 {
+            self.forward = true;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeedPortal {
+    pub used: bool,
+    pub speed_change: f32,
+    pub hitbox: Hitbox,
+}
+
+impl SpeedPortal {
+    pub fn new(x: f32, y: f32, speed_change: f32) -> SpeedPortal {
+        SpeedPortal {
+            used: false,
+            speed_change,
+            hitbox: Hitbox::new(x, y, world::PORTAL_WIDTH, world::PORTAL_HEIGHT),
+        }
+    }
+
+    pub fn update(&mut self, player: &mut Player) {
+        if player.hitbox.collides(&self.hitbox) {
+            if self.used {
+                return;
+            }
+            player.x_speed_mult *= self.speed_change;
+            self.used = true;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Portal {
+    pub used: bool,
+    pub kind: world::PortalKind,
+    pub hitbox: Hitbox,
+}
+
+impl Portal {
+    pub fn new(x: f32, y: f32, kind: world::PortalKind) -> Portal {
+        Portal {
+            used: false,
+            kind,
+            hitbox: Hitbox::new(x, y, world::PORTAL_WIDTH, world::PORTAL_HEIGHT),
+        }
+    }
+
+    pub fn update(&mut self, player: &mut Player) {
+        if self.used || !player.hitbox.collides(&self.hitbox) {
+            return;
+        }
+        match self.kind {
+            world::PortalKind::Gravity { inverted } => player.gravity_inverted = inverted,
+            world::PortalKind::Size { size } => player.resize(size),
+            world::PortalKind::Mirror { mirrored } => player.mirrored = mirrored,
+            world::PortalKind::Speed { speed } => player.x_speed_mult = speed,
+        }
+        self.used = true;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub x: f32,
+    pub reached: bool,
+}
+
+// What the player did during a tick
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Input {
+    // Clicked or pressed jump, which flips the direction the player is flying in
+    pub toggle: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Player {
+    pub x: f32,
+    pub y: f32,
+    pub vy: f32,
+    pub is_facing_up: bool,
+    pub x_speed_mult: f32,
+    // What the portals did to the player
+    pub gravity_inverted: bool,
+    pub mirrored: bool,
+    pub size: world::PlayerSize,
+    pub hitbox: Hitbox,
+}
+
+impl Player {
+    pub fn new(x: f32, y: f32) -> Player {
+        Player {
+            x,
+            y,
+            vy: 0.0,
+            is_facing_up: true, // Start out flying because the click from the titlescreen persists
+            x_speed_mult: 3.5,
+            gravity_inverted: false,
+            mirrored: false,
+            size: world::PlayerSize::Normal,
+            hitbox: Hitbox::new(x, y, world::PLAYER_WIDTH, world::PLAYER_HEIGHT),
+        }
+    }
+
+    pub fn update(&mut self, world: &World, input: Input) -> bool {
+        if world.player_hit_check(self) {
+            return true; // Player dies
+        }
+
+        // Movement logic only runs if no collision
+        if input.toggle {
+            self.is_facing_up = !self.is_facing_up;
+        }
+
+        if self.is_facing_up != self.gravity_inverted {
+            self.vy -= PLAYER_SPEED;
+        } else {
+            self.vy += PLAYER_SPEED;
+        }
+
+        self.y += self.vy;
+        self.hitbox.y = self.y;
+
+        let direction = if self.mirrored { -1.0 } else { 1.0 };
+        self.x += PLAYER_SPEED * self.x_speed_mult * direction;
+        self.hitbox.x = self.x;
+
+        self.vy *= 0.9;
+
+        false // Player survives
+    }
+
+    // Grows or shrinks around the middle, so going through a size portal doesn't shove the
+    // player into whatever is right above or below them
+    pub fn resize(&mut self, size: world::PlayerSize) {
+        let width = world::PLAYER_WIDTH * size.scale();
+        let height = world::PLAYER_HEIGHT * size.scale();
+        self.y += (self.hitbox.height - height) / 2.0;
+        self.size = size;
+        self.hitbox = Hitbox::new(self.x, self.y, width, height);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct World {
+    pub objects: Vec<Hitbox>,
+    pub poly_objects: Vec<PolygonHitbox>,
+    pub moving_objects: Vec<MovingObject>,
+    pub speed_increases: Vec<SpeedPortal>,
+    // Where the finish line is, if there is one
+    pub finish: Option<f32>,
+    pub checkpoints: Vec<Checkpoint>,
+    pub portals: Vec<Portal>,
+}
+
+impl World {
+    pub fn from_def(def: &world::World) -> World {
+        let vec = |p: world::Point| vec2(p.x, p.y);
+        World {
+            objects: def.objects.iter().map(|o| {
+                Hitbox::new(o.x, o.y, o.width, o.height)
+            }).collect(),
+            poly_objects: def.poly_objects.iter().map(|o| {
+                PolygonHitbox::new(o.points.iter().copied().map(vec).collect())
+            }).collect(),
+            moving_objects: def.moving_objects.iter().map(|o| {
+                MovingObject::new(vec(o.from), vec(o.to), o.width, o.height, o.speed)
+            }).collect(),
+            speed_increases: def.speed_increases.iter().map(|o| {
+                SpeedPortal::new(o.x, o.y, o.speed_change)
+            }).collect(),
+            finish: def.finish.map(|f| f.x),
+            checkpoints: def.checkpoints.iter().map(|c| Checkpoint { x: c.x, reached: false }).collect(),
+            portals: def.portals.iter().map(|p| Portal::new(p.x, p.y, p.kind)).collect(),
+        }
+    }
+
+    pub fn to_def(&self) -> world::World {
+        let point = |v: Vec2| world::Point { x: v.x, y: v.y };
+        world::World {
+            objects: self.objects.iter().map(|o| world::Rect {
+                x: o.x,
+                y: o.y,
+                width: o.width,
+                height: o.height,
+            }).collect(),
+            poly_objects: self.poly_objects.iter().map(|o| world::Polygon {
+                points: o.points.iter().copied().map(point).collect(),
+            }).collect(),
+            moving_objects: self.moving_objects.iter().map(|o| world::MovingObject {
+                from: point(o.from),
+                to: point(o.to),
+                width: o.hitbox.width,
+                height: o.hitbox.height,
+                speed: o.speed,
+            }).collect(),
+            speed_increases: self.speed_increases.iter().map(|o| world::SpeedPortal {
+                x: o.hitbox.x,
+                y: o.hitbox.y,
+                speed_change: o.speed_change,
+            }).collect(),
+            finish: self.finish.map(|x| world::FinishLine { x }),
+            checkpoints: self.checkpoints.iter().map(|c| world::Checkpoint { x: c.x }).collect(),
+            portals: self.portals.iter().map(|p| world::Portal {
+                x: p.hitbox.x,
+                y: p.hitbox.y,
+                kind: p.kind,
+            }).collect(),
+        }
+    }
+
+    pub fn player_hit_check(&self, player: &Player) -> bool {
+        for object in &self.objects {
+            if player.hitbox.collides(object) {
+                return true;
+            }
+        }
+        for object in &self.poly_objects {
+            if object.collides(&PolygonHitbox::new(vec![
+                vec2(player.x, player.y),
+                vec2(player.x + player.hitbox.width, player.y),
+                vec2(player.x + player.hitbox.width, player.y + player.hitbox.height),
+                vec2(player.x, player.y + player.hitbox.height),
+            ])) {
+                return true;
+            }
+        }
+        for object in &self.moving_objects {
+            if player.hitbox.collides(&object.hitbox) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // The front of the player touched the finish line
+    pub fn player_finished(&self, player: &Player) -> bool {
+        self.finish.is_some_and(|x| player.x + player.hitbox.width >= x)
+    }
+
+    // Marks the checkpoints the player got past, returns whether there were any new ones
+    pub fn reach_checkpoints(&mut self, player: &Player) -> bool {
+        let mut reached = false;
+        for checkpoint in &mut self.checkpoints {
+            if !checkpoint.reached && player.x >= checkpoint.x {
+                checkpoint.reached = true;
+                reached = true;
+            }
+        }
+        reached
+    }
+
+    pub fn update(&mut self, player: &mut Player) {
+        for object in &mut self.moving_objects {
+            object.update();
+        }
+
+        for object in &mut self.speed_increases {
+            object.update(player);
+        }
+
+        for object in &mut self.portals {
+            object.update(player);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Alive,
+    Died,
+    // Reached the finish line, the tick it happened on still counts
+    Finished,
+    // Still alive, and just got past a checkpoint
+    Checkpoint,
+}
+
+// Everything about an attempt at one moment: where the player is and how fast they're going,
+// which portals were used, where the movers are and which way they're going. Practice mode's
+// checkpoints go back to one of these
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub player: Player,
+    pub world: World,
+    pub tick: u32,
+}
+
+// One attempt at a level
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    pub player: Player,
+    pub world: World,
+    // Ticks survived so far, which is also the score
+    pub tick: u32,
+    // The world as it was at the start, to go back to when the player dies
+    start: World,
+    // Where every attempt starts
+    spawn: world::Point,
+}
+
+impl Simulation {
+    pub fn new(def: &world::World) -> Simulation {
+        Simulation::starting_at(def, world::SPAWN)
+    }
+
+    // For trying out the level from somewhere other than the start, like the editor does
+    pub fn starting_at(def: &world::World, spawn: world::Point) -> Simulation {
+        let world = World::from_def(def);
+        Simulation {
+            player: Player::new(spawn.x, spawn.y),
+            world: world.clone(),
+            tick: 0,
+            start: world,
+            spawn,
+        }
+    }
+
+    // Advances the game by one tick. After `Step::Died` nothing moves anymore until `restart`.
+    // `Step::Finished` ends the attempt too, but stepping further just carries on past the line
+    pub fn step(&mut self, input: Input) -> Step {
+        if self.player.update(&self.world, input) {
+            return Step::Died;
+        }
+        self.world.update(&mut self.player);
+        self.tick += 1;
+        if self.world.player_finished(&self.player) {
+            return Step::Finished;
+        }
+        if self.world.reach_checkpoints(&self.player) {
+            return Step::Checkpoint;
+        }
+        Step::Alive
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            player: self.player.clone(),
+            world: self.world.clone(),
+            tick: self.tick,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.player = snapshot.player.clone();
+        self.world = snapshot.world.clone();
+        self.tick = snapshot.tick;
+    }
+
+    // Puts everything back where it was, movers included, so every attempt plays out the same
+    pub fn restart(&mut self) {
+        self.player = Player::new(self.spawn.x, self.spawn.y);
+        self.world = self.start.clone();
+        self.tick = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mover_going_nowhere_stays_put() {
+        let mut mover = MovingObject::new(vec2(10.0, 20.0), vec2(10.0, 20.0), 50.0, 50.0, 3.0);
+        for _ in 0..100 {
+            mover.update();
+        }
+        assert_eq!(mover.hitbox.get_pos(), vec2(10.0, 20.0));
+    }
+
+    // Zigzags around y = 0, so the player never hits anything above or below
+    fn zigzag(tick: u32) -> Input {
+        Input { toggle: tick % 8 == 4 }
+    }
+
+    // Nothing to crash into, a speed boost and a gravity portal in the way and a mover off to the side
+    fn portals_level() -> world::World {
+        let point = |x, y| world::Point { x, y };
+        world::World {
+            objects: Vec::new(),
+            poly_objects: Vec::new(),
+            moving_objects: vec![world::MovingObject { from: point(0.0, 300.0), to: point(0.0, 400.0), width: 50.0, height: 50.0, speed: 2.0 }],
+            speed_increases: vec![world::SpeedPortal { x: 100.0, y: -64.0, speed_change: 2.0 }],
+            finish: None,
+            checkpoints: Vec::new(),
+            portals: vec![world::Portal { x: 300.0, y: -64.0, kind: world::PortalKind::Gravity { inverted: true } }],
+        }
+    }
+
+    fn run(sim: &mut Simulation, ticks: u32) {
+        for _ in 0..ticks {
+            assert_eq!(sim.step(zigzag(sim.tick)), Step::Alive);
+        }
+    }
+
+    #[test]
+    fn restoring_a_snapshot_puts_everything_back() {
+        let mut sim = Simulation::new(&portals_level());
+        run(&mut sim, 40);
+        let snapshot = sim.snapshot();
+        assert!(sim.world.speed_increases[0].used);
+        assert!(!sim.world.portals[0].used);
+        let copy = sim.clone();
+
+        run(&mut sim, 40);
+        assert!(sim.world.portals[0].used);
+        // The mover turned around at the end of its path
+        assert!(!sim.world.moving_objects[0].forward);
+        assert_ne!(sim.player, snapshot.player);
+        assert_ne!(sim.world, snapshot.world);
+
+        sim.restore(&snapshot);
+        assert_eq!(sim.player, snapshot.player);
+        assert_eq!(sim.player.x_speed_mult, 7.0);
+        assert!(!sim.player.gravity_inverted);
+        assert_eq!(sim.world, snapshot.world);
+        assert!(sim.world.moving_objects[0].forward);
+        assert_eq!(sim.tick, 40);
+
+        // Plays out exactly like it did the first time
+        let mut copy = copy;
+        for _ in 0..100 {
+            let input = zigzag(sim.tick);
+            assert_eq!(sim.step(input), copy.step(input));
+            assert_eq!((&sim.player, &sim.world, sim.tick), (&copy.player, &copy.world, copy.tick));
+        }
+    }
+
+    // Flies the player into a `kind` portal, undoes what it did with `undo` and flies them into it
+    // again. Returns the player after each time
+    fn pass_twice(kind: world::PortalKind, undo: impl Fn(&mut Player)) -> (Player, Player) {
+        let mut portal = Portal::new(0.0, -64.0, kind);
+        let mut player = Player::new(0.0, 0.0);
+        let untouched = player.clone();
+        portal.update(&mut player);
+        let first = player.clone();
+        assert!(portal.used);
+        undo(&mut player);
+        assert_eq!(player, untouched);
+        portal.update(&mut player);
+        (first, player)
+    }
+
+    #[test]
+    fn gravity_portals_work_once() {
+        let (first, second) = pass_twice(world::PortalKind::Gravity { inverted: true }, |p| p.gravity_inverted = false);
+        assert!(first.gravity_inverted);
+        assert!(!second.gravity_inverted);
+    }
+
+    #[test]
+    fn size_portals_work_once() {
+        let (first, second) = pass_twice(world::PortalKind::Size { size: world::PlayerSize::Mini }, |p| p.resize(world::PlayerSize::Normal));
+        assert_eq!(first.size, world::PlayerSize::Mini);
+        // Shrinks around the middle
+        assert_eq!(first.hitbox, Hitbox::new(0.0, 6.0, 16.0, 12.0));
+        assert_eq!(second.size, world::PlayerSize::Normal);
+    }
+
+    #[test]
+    fn mirror_portals_work_once() {
+        let (first, second) = pass_twice(world::PortalKind::Mirror { mirrored: true }, |p| p.mirrored = false);
+        assert!(first.mirrored);
+        assert!(!second.mirrored);
+    }
+
+    #[test]
+    fn set_speed_portals_work_once() {
+        let (first, second) = pass_twice(world::PortalKind::Speed { speed: 5.0 }, |p| p.x_speed_mult = 3.5);
+        assert_eq!(first.x_speed_mult, 5.0);
+        assert_eq!(second.x_speed_mult, 3.5);
+    }
+
+    #[test]
+    fn flying_back_through_a_mirror_portal_changes_nothing() {
+        let mut level = portals_level();
+        level.portals = vec![world::Portal { x: 100.0, y: -64.0, kind: world::PortalKind::Mirror { mirrored: true } }];
+        level.speed_increases.clear();
+        let mut sim = Simulation::new(&level);
+        run(&mut sim, 30);
+        assert!(sim.player.mirrored);
+        let turned_at = sim.player.x;
+        // Back through the portal and out the other side, still going left
+        run(&mut sim, 60);
+        assert!(sim.player.mirrored);
+        assert!(sim.player.x < turned_at - 100.0);
+    }
+
+    #[test]
+    fn portals_only_work_when_flown_through() {
+        let mut portal = Portal::new(500.0, -64.0, world::PortalKind::Mirror { mirrored: true });
+        let mut player = Player::new(0.0, 0.0);
+        portal.update(&mut player);
+        assert!(!portal.used);
+        assert!(!player.mirrored);
+    }
+
+    #[test]
+    fn starting_somewhere_else_restarts_there() {
+        let level = portals_level();
+        let mut sim = Simulation::starting_at(&level, world::Point { x: 500.0, y: 0.0 });
+        let start = sim.clone();
+        assert_eq!((sim.player.x, sim.player.y), (500.0, 0.0));
+        run(&mut sim, 30);
+        sim.restart();
+        assert_eq!((&sim.player, &sim.world, sim.tick), (&start.player, &start.world, 0));
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+// One verified submission. Unlike the leaderboard, which only has everyone's best, every run is
+// kept so there's a history to look back on
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Run {
+    pub player_id: String,
+    pub level: String,
+    pub score: u32,
+    // How many times the player died on the level before submitting
+    pub attempts: u32,
+    // Ticks spent playing the level, over all those attempts
+    pub playtime: u32,
+    // Seconds since the unix epoch
+    pub time: u64,
+    // Reached the finish line. Runs from before there were finish lines never did
+    #[serde(default)]
+    pub finished: bool,
+}
+
+// The best score so far after every run, for drawing how a player got better
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Progress {
+    pub time: u64,
+    pub score: u32,
+    pub best: u32,
+}
+
+// What /stats/{player} returns, for a single level
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerStats {
+    pub player: String,
+    pub level: String,
+    pub runs: usize,
+    pub best: u32,
+    pub average: f32,
+    // Ticks
+    pub total_playtime: u64,
+    pub total_attempts: u64,
+    // How much better the best is than the first run
+    pub improvement: u32,
+    pub progress: Vec<Progress>,
+    // How many runs reached the finish line, and how fast the fastest one did
+    #[serde(default)]
+    pub completions: usize,
+    #[serde(default)]
+    pub best_completion: Option<u32>,
+}
+
+// None if the player never submitted anything on the level. `runs` are oldest first
+pub fn player_stats(runs: &[Run], player_id: &str, player: &str, level: &str) -> Option<PlayerStats> {
+    let runs: Vec<&Run> = runs.iter().filter(|r| r.player_id == player_id && r.level == level).collect();
+    let first = runs.first()?;
+
+    let mut best = 0;
+    let progress: Vec<Progress> = runs
+        .iter()
+        .map(|run| {
+            best = best.max(run.score);
+            Progress { time: run.time, score: run.score, best }
+        })
+        .collect();
+
+    Some(PlayerStats {
+        player: player.to_owned(),
+        level: level.to_owned(),
+        runs: runs.len(),
+        best,
+        average: runs.iter().map(|r| r.score as f32).sum::<f32>() / runs.len() as f32,
+        total_playtime: runs.iter().fold(0u64, |total, r| total.saturating_add(r.playtime as u64)),
+        total_attempts: runs.iter().fold(0u64, |total, r| total.saturating_add(r.attempts as u64)),
+        improvement: best - first.score,
+        progress,
+        completions: runs.iter().filter(|r| r.finished).count(),
+        best_completion: runs.iter().filter(|r| r.finished).map(|r| r.score).min(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_dont_overflow() {
+        let run = Run {
+            player_id: "id".to_owned(),
+            level: "classic".to_owned(),
+            score: 100,
+            attempts: u32::MAX,
+            playtime: u32::MAX,
+            time: 0,
+            finished: false,
+        };
+        let stats = player_stats(&[run.clone(), run], "id", "player", "classic").unwrap();
+        assert_eq!(stats.total_playtime, u32::MAX as u64 * 2);
+        assert_eq!(stats.total_attempts, u32::MAX as u64 * 2);
+    }
+}
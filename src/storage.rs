@@ -0,0 +1,134 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    env,
+    fs::{create_dir_all, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+// Where the server keeps its stuff between restarts.
+//
+// Picked with the STORAGE_BACKEND env var:
+//   "jsonl" (default) - one append-only JSON-lines file per collection inside STORAGE_DIR
+//                       (defaults to "data")
+//   "memory"          - nothing is written anywhere, everything is gone on restart
+#[derive(Clone, Debug)]
+pub enum Storage {
+    Memory,
+    JsonLines(PathBuf),
+}
+
+impl Storage {
+    pub fn from_env() -> Storage {
+        match env::var("STORAGE_BACKEND").as_deref() {
+            Ok("memory") => Storage::Memory,
+            Ok("jsonl") | Err(_) => {
+                Storage::JsonLines(env::var("STORAGE_DIR").unwrap_or("data".to_owned()).into())
+            }
+            Ok(other) => panic!("Unknown STORAGE_BACKEND \"{}\" (expected \"jsonl\" or \"memory\").", other),
+        }
+    }
+
+    fn path(dir: &Path, collection: &str) -> PathBuf {
+        dir.join(format!("{}.jsonl", collection))
+    }
+
+    // Reads back every record that was ever appended to the collection, oldest first.
+    // Lines that don't parse (e.g. half written because the server died mid-write) are skipped.
+    pub fn load<T: DeserializeOwned>(&self, collection: &str) -> Vec<T> {
+        let dir = match self {
+            Storage::Memory => return Vec::new(),
+            Storage::JsonLines(dir) => dir,
+        };
+
+        let file = match File::open(Self::path(dir, collection)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => panic!("Failed to open the {} storage file ({}).", collection, e),
+        };
+
+        let mut records = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Stopped reading {} at line {} ({})", collection, i + 1, e);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => eprintln!("Skipping bad record in {} at line {} ({})", collection, i + 1, e),
+            }
+        }
+        records
+    }
+
+    pub fn append<T: Serialize>(&self, collection: &str, record: &T) -> io::Result<()> {
+        let dir = match self {
+            Storage::Memory => return Ok(()),
+            Storage::JsonLines(dir) => dir,
+        };
+
+        create_dir_all(dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::path(dir, collection))?;
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        // One write call per record so a crash can only ever leave the last line half written
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::fs;
+
+    // A fresh directory for one test, nothing else uses the same name
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("hardest-game-ever-{}-{:08x}", name, rand::random::<u32>()))
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Record {
+        n: u32,
+    }
+
+    #[test]
+    fn reads_back_what_was_appended() {
+        let dir = temp_dir("storage");
+        let storage = Storage::JsonLines(dir.clone());
+        storage.append("records", &Record { n: 1 }).unwrap();
+        storage.append("records", &Record { n: 2 }).unwrap();
+        assert_eq!(storage.load::<Record>("records"), vec![Record { n: 1 }, Record { n: 2 }]);
+        assert!(storage.load::<Record>("nothing").is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn skips_a_half_written_last_line() {
+        let dir = temp_dir("storage");
+        let storage = Storage::JsonLines(dir.clone());
+        storage.append("records", &Record { n: 1 }).unwrap();
+        // What a crash in the middle of the next write leaves behind
+        let mut file = OpenOptions::new().append(true).open(Storage::path(&dir, "records")).unwrap();
+        file.write_all(b"{\"n\": 2").unwrap();
+        assert_eq!(storage.load::<Record>("records"), vec![Record { n: 1 }]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_keeps_nothing() {
+        let storage = Storage::Memory;
+        storage.append("records", &Record { n: 1 }).unwrap();
+        assert!(storage.load::<Record>("records").is_empty());
+    }
+}
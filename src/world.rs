@@ -0,0 +1,610 @@
+use macroquad::math::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{error::Error, fmt};
+
+use crate::geometry::{line_intersect, point_in_polygon};
+
+// The level format, shared by the server (which serves it) and the client (which plays it)
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+// A wall
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+// A wall of any shape
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Polygon {
+    pub points: Vec<Point>,
+}
+
+// A wall that goes back and forth between `from` and `to`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MovingObject {
+    pub from: Point,
+    pub to: Point,
+    pub width: f32,
+    pub height: f32,
+    pub speed: f32,
+}
+
+// Multiplies the player's speed by `speed_change` the first time they fly through it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SpeedPortal {
+    pub x: f32,
+    pub y: f32,
+    pub speed_change: f32,
+}
+
+// How big the player is, `Normal` is how they start out
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerSize {
+    Mini,
+    Normal,
+    Large,
+}
+
+impl PlayerSize {
+    pub const ALL: [PlayerSize; 3] = [PlayerSize::Mini, PlayerSize::Normal, PlayerSize::Large];
+
+    pub fn scale(self) -> f32 {
+        match self {
+            PlayerSize::Mini => 0.5,
+            PlayerSize::Normal => 1.0,
+            PlayerSize::Large => 1.5,
+        }
+    }
+}
+
+// What flying through a portal does. They all set something rather than toggle it, so a portal
+// does the same thing however the player got to it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PortalKind {
+    // Swaps which way is up, clicking still flips the direction but going "up" goes down
+    Gravity { inverted: bool },
+    Size { size: PlayerSize },
+    // The player flies right to left
+    Mirror { mirrored: bool },
+    // Sets the speed to exactly `speed`, unlike speed portals which multiply it
+    Speed { speed: f32 },
+}
+
+// e.g. {"x": 800, "y": -64, "kind": "gravity", "inverted": true}. Every one is used once per
+// attempt, the first time the player flies through it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Portal {
+    pub x: f32,
+    pub y: f32,
+    #[serde(flatten)]
+    pub kind: PortalKind,
+}
+
+// The end of the level, a line going all the way up and down at `x`. Reaching it finishes the level
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct FinishLine {
+    pub x: f32,
+}
+
+// Saves the player's progress in practice mode once they get past `x`. Normal runs always start
+// from the beginning, so everyone's scores are for the whole level
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub x: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct World {
+    pub objects: Vec<Rect>,
+    pub poly_objects: Vec<Polygon>,
+    pub moving_objects: Vec<MovingObject>,
+    pub speed_increases: Vec<SpeedPortal>,
+    // Levels without one go on until the player dies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish: Option<FinishLine>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checkpoints: Vec<Checkpoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub portals: Vec<Portal>,
+}
+
+impl World {
+    // The original level, from back when there was only one
+    pub fn classic() -> World {
+        let point = |x, y| Point { x, y };
+        World {
+            objects: vec![
+                Rect { x: 0.0, y: 250.0, width: 5000.0, height: 50.0 },
+                Rect { x: 0.0, y: -300.0, width: 5000.0, height: 50.0 },
+
+                Rect { x: 500.0, y: 15.0, width: 50.0, height: 235.0 },
+                Rect { x: 625.0, y: -250.0, width: 50.0, height: 235.0 },
+            ],
+            poly_objects: vec![
+                Polygon {
+                    points: vec![
+                        point(775.0, -80.0), // Top left
+                        point(775.0, 250.0), // Bottom left
+                        point(1075.0, 250.0), // Bottom right
+                        point(1075.0, 0.0), // Top right (1)
+                        point(975.0, -80.0), // Top right (2)
+                    ],
+                },
+            ],
+            moving_objects: vec![
+                MovingObject { from: point(1100.0, -225.0), to: point(1200.0, 150.0), width: 50.0, height: 100.0, speed: 3.0 },
+                MovingObject { from: point(1300.0, 150.0), to: point(1400.0, -225.0), width: 50.0, height: 100.0, speed: 3.0 },
+                MovingObject { from: point(1500.0, 0.0), to: point(1600.0, 0.0), width: 100.0, height: 50.0, speed: 3.0 },
+            ],
+            speed_increases: vec![
+                SpeedPortal { x: 1075.0, y: -200.0, speed_change: 2.0 },
+            ],
+            finish: None,
+            checkpoints: Vec::new(),
+            portals: Vec::new(),
+        }
+    }
+}
+
+// Where (and how big) the player is when an attempt starts, nothing is allowed to be there
+pub const SPAWN: Point = Point { x: 0.0, y: 0.0 };
+pub const PLAYER_WIDTH: f32 = 32.0;
+pub const PLAYER_HEIGHT: f32 = 24.0;
+
+// Every portal is the same size, only where it is and what it does are in the level
+pub const PORTAL_WIDTH: f32 = 64.0;
+pub const PORTAL_HEIGHT: f32 = 128.0;
+
+// Something wrong with a level. `path` points at the offending bit, e.g. "moving_objects[1].to"
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Problem {
+    MissingField { path: String },
+    WrongType { path: String, expected: String },
+    TooFewPoints { path: String, points: usize },
+    ZeroLengthPath { path: String },
+    NegativeSize { path: String },
+    BlocksSpawn { path: String },
+    FinishAtSpawn { path: String },
+    NotPositive { path: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::MissingField { path } => write!(f, "{} is missing", path),
+            Problem::WrongType { path, expected } => write!(f, "{} should be {}", path, expected),
+            Problem::TooFewPoints { path, points } => {
+                write!(f, "{} has {} points, a polygon needs at least 3", path, points)
+            }
+            Problem::ZeroLengthPath { path } => write!(f, "{} starts and ends at the same spot", path),
+            Problem::NegativeSize { path } => write!(f, "{} has a negative size", path),
+            Problem::BlocksSpawn { path } => write!(f, "{} is in the way of the spawn point", path),
+            Problem::FinishAtSpawn { path } => write!(f, "{} is at or behind the spawn point", path),
+            Problem::NotPositive { path } => write!(f, "{} has to be more than 0", path),
+        }
+    }
+}
+
+// Every problem with a level, not just the first one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LevelError {
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid level: ")?;
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LevelError {}
+
+impl World {
+    // Parses and validates a level
+    pub fn parse(data: &str) -> Result<World, LevelError> {
+        match serde_json::from_str(data) {
+            Ok(value) => World::from_value(value),
+            Err(e) => Err(LevelError {
+                problems: vec![Problem::WrongType { path: "level".to_owned(), expected: format!("valid JSON ({})", e) }],
+            }),
+        }
+    }
+
+    pub fn from_value(value: Value) -> Result<World, LevelError> {
+        let world: World = match serde_json::from_value(value.clone()) {
+            Ok(world) => world,
+            Err(e) => {
+                // serde gives up at the first problem, so go through it by hand to find all of them
+                let mut problems = Vec::new();
+                check_structure(&value, &mut problems);
+                if problems.is_empty() {
+                    problems.push(Problem::WrongType { path: "level".to_owned(), expected: format!("a world ({})", e) });
+                }
+                return Err(LevelError { problems });
+            }
+        };
+        world.validate()?;
+        Ok(world)
+    }
+
+    pub fn validate(&self) -> Result<(), LevelError> {
+        let mut problems = Vec::new();
+        let spawn = Rect { x: SPAWN.x, y: SPAWN.y, width: PLAYER_WIDTH, height: PLAYER_HEIGHT };
+
+        for (i, object) in self.objects.iter().enumerate() {
+            let path = format!("objects[{}]", i);
+            if object.width < 0.0 || object.height < 0.0 {
+                problems.push(Problem::NegativeSize { path: path.clone() });
+            }
+            if rects_overlap(object, &spawn) {
+                problems.push(Problem::BlocksSpawn { path });
+            }
+        }
+        for (i, object) in self.poly_objects.iter().enumerate() {
+            let path = format!("poly_objects[{}]", i);
+            if object.points.len() < 3 {
+                problems.push(Problem::TooFewPoints { path, points: object.points.len() });
+            } else if polygon_overlaps(object, &spawn) {
+                problems.push(Problem::BlocksSpawn { path });
+            }
+        }
+        for (i, object) in self.moving_objects.iter().enumerate() {
+            let path = format!("moving_objects[{}]", i);
+            if object.from == object.to {
+                problems.push(Problem::ZeroLengthPath { path: path.clone() });
+            }
+            if object.width < 0.0 || object.height < 0.0 {
+                problems.push(Problem::NegativeSize { path: path.clone() });
+            }
+            // It'd never move, or go the wrong way
+            if object.speed <= 0.0 {
+                problems.push(Problem::NotPositive { path: format!("{}.speed", path) });
+            }
+            let start = Rect { x: object.from.x, y: object.from.y, width: object.width, height: object.height };
+            if rects_overlap(&start, &spawn) {
+                problems.push(Problem::BlocksSpawn { path });
+            }
+        }
+        for (i, portal) in self.speed_increases.iter().enumerate() {
+            // Same as set-speed portals below
+            if portal.speed_change <= 0.0 {
+                problems.push(Problem::NotPositive { path: format!("speed_increases[{}].speed_change", i) });
+            }
+        }
+        for (i, portal) in self.portals.iter().enumerate() {
+            // The player would stop dead, or go backwards
+            if let PortalKind::Speed { speed } = portal.kind {
+                if speed <= 0.0 {
+                    problems.push(Problem::NotPositive { path: format!("portals[{}].speed", i) });
+                }
+            }
+        }
+        if let Some(finish) = self.finish {
+            if finish.x <= spawn.x + spawn.width {
+                problems.push(Problem::FinishAtSpawn { path: "finish".to_owned() });
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(LevelError { problems })
+        }
+    }
+}
+
+fn rects_overlap(a: &Rect, b: &Rect) -> bool {
+    a.x < b.x + b.width &&
+    a.x + a.width > b.x &&
+    a.y < b.y + b.height &&
+    a.y + a.height > b.y
+}
+
+fn polygon_overlaps(polygon: &Polygon, rect: &Rect) -> bool {
+    let points: Vec<Vec2> = polygon.points.iter().map(|p| vec2(p.x, p.y)).collect();
+    let corners = [
+        vec2(rect.x, rect.y),
+        vec2(rect.x + rect.width, rect.y),
+        vec2(rect.x + rect.width, rect.y + rect.height),
+        vec2(rect.x, rect.y + rect.height),
+    ];
+    for i in 0..points.len() {
+        for j in 0..corners.len() {
+            if line_intersect(points[i], points[(i + 1) % points.len()], corners[j], corners[(j + 1) % corners.len()]) {
+                return true;
+            }
+        }
+    }
+    // Completely inside the polygon (or the other way round)
+    point_in_polygon(corners[0], &points) || point_in_polygon(points[0], &corners)
+}
+
+fn check_structure(value: &Value, problems: &mut Vec<Problem>) {
+    if !value.is_object() {
+        problems.push(Problem::WrongType { path: "level".to_owned(), expected: "an object".to_owned() });
+        return;
+    }
+    check_list(value, "", "objects", problems, |object, path, problems| {
+        check_numbers(object, path, &["x", "y", "width", "height"], problems);
+    });
+    check_list(value, "", "poly_objects", problems, |object, path, problems| {
+        check_list(object, path, "points", problems, |point, path, problems| {
+            check_numbers(point, path, &["x", "y"], problems);
+        });
+    });
+    check_list(value, "", "moving_objects", problems, |object, path, problems| {
+        for point in ["from", "to"] {
+            match object.get(point) {
+                None => problems.push(Problem::MissingField { path: format!("{}.{}", path, point) }),
+                Some(p) => check_numbers(p, &format!("{}.{}", path, point), &["x", "y"], problems),
+            }
+        }
+        check_numbers(object, path, &["width", "height", "speed"], problems);
+    });
+    check_list(value, "", "speed_increases", problems, |object, path, problems| {
+        check_numbers(object, path, &["x", "y", "speed_change"], problems);
+    });
+    // Optional, so only check them if they're there
+    if let Some(finish) = value.get("finish").filter(|f| !f.is_null()) {
+        check_numbers(finish, "finish", &["x"], problems);
+    }
+    if value.get("checkpoints").is_some() {
+        check_list(value, "", "checkpoints", problems, |object, path, problems| {
+            check_numbers(object, path, &["x"], problems);
+        });
+    }
+    if value.get("portals").is_some() {
+        check_list(value, "", "portals", problems, check_portal);
+    }
+}
+
+fn check_portal(object: &Value, path: &str, problems: &mut Vec<Problem>) {
+    check_numbers(object, path, &["x", "y"], problems);
+    let Some(object) = object.as_object() else {
+        return; // check_numbers already complained
+    };
+    let expect = |name: &str, expected: &str, ok: fn(&Value) -> bool, problems: &mut Vec<Problem>| {
+        let path = format!("{}.{}", path, name);
+        match object.get(name) {
+            None => problems.push(Problem::MissingField { path }),
+            Some(v) if !ok(v) => problems.push(Problem::WrongType { path, expected: expected.to_owned() }),
+            Some(_) => {}
+        }
+    };
+    let sizes = |v: &Value| PlayerSize::deserialize(v).is_ok();
+    match object.get("kind").and_then(Value::as_str) {
+        Some("gravity") => expect("inverted", "true or false", Value::is_boolean, problems),
+        Some("size") => expect("size", "\"mini\", \"normal\" or \"large\"", sizes, problems),
+        Some("mirror") => expect("mirrored", "true or false", Value::is_boolean, problems),
+        Some("speed") => expect("speed", "a number", Value::is_number, problems),
+        _ => expect("kind", "\"gravity\", \"size\", \"mirror\" or \"speed\"", |_| false, problems),
+    }
+}
+
+// Calls `check` on every item of the list `parent.name`
+fn check_list(
+    parent: &Value,
+    parent_path: &str,
+    name: &str,
+    problems: &mut Vec<Problem>,
+    check: impl Fn(&Value, &str, &mut Vec<Problem>),
+) {
+    let path = if parent_path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", parent_path, name)
+    };
+    match parent.get(name) {
+        None => problems.push(Problem::MissingField { path }),
+        Some(Value::Array(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                check(item, &format!("{}[{}]", path, i), problems);
+            }
+        }
+        Some(_) => problems.push(Problem::WrongType { path, expected: "a list".to_owned() }),
+    }
+}
+
+fn check_numbers(object: &Value, path: &str, names: &[&str], problems: &mut Vec<Problem>) {
+    if !object.is_object() {
+        problems.push(Problem::WrongType { path: path.to_owned(), expected: "an object".to_owned() });
+        return;
+    }
+    for name in names {
+        match object.get(*name) {
+            None => problems.push(Problem::MissingField { path: format!("{}.{}", path, name) }),
+            Some(v) if !v.is_number() => {
+                problems.push(Problem::WrongType { path: format!("{}.{}", path, name), expected: "a number".to_owned() })
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{levels::LevelFile, sim};
+    use std::fs;
+
+    // Every level the server comes with
+    fn level_files() -> Vec<(String, World)> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/levels");
+        let mut levels = Vec::new();
+        for entry in fs::read_dir(dir).unwrap().flatten() {
+            let path = entry.path();
+            let file: LevelFile = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            let world = World::from_value(file.world).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            levels.push((path.display().to_string(), world));
+        }
+        assert!(!levels.is_empty());
+        levels
+    }
+
+    fn round_trip(world: &World) -> World {
+        World::parse(&serde_json::to_string(world).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn level_files_round_trip() {
+        for (path, world) in level_files() {
+            assert_eq!(round_trip(&world), world, "{}", path);
+        }
+    }
+
+    #[test]
+    fn classic_round_trips() {
+        let classic = World::classic();
+        assert_eq!(round_trip(&classic), classic);
+    }
+
+    // The classic level with every optional part too
+    fn everything() -> World {
+        let portal = |x, kind| Portal { x, y: -64.0, kind };
+        World {
+            finish: Some(FinishLine { x: 4000.0 }),
+            checkpoints: vec![Checkpoint { x: 2000.0 }],
+            portals: vec![
+                portal(1000.0, PortalKind::Gravity { inverted: true }),
+                portal(1200.0, PortalKind::Size { size: PlayerSize::Mini }),
+                portal(1400.0, PortalKind::Mirror { mirrored: false }),
+                portal(1600.0, PortalKind::Speed { speed: 5.0 }),
+            ],
+            ..World::classic()
+        }
+    }
+
+    #[test]
+    fn optional_parts_round_trip() {
+        let world = everything();
+        assert_eq!(round_trip(&world), world);
+    }
+
+    // Every problem `world` has
+    fn problems(world: World) -> Vec<Problem> {
+        world.validate().err().map(|e| e.problems).unwrap_or_default()
+    }
+
+    fn path(path: &str) -> String {
+        path.to_owned()
+    }
+
+    #[test]
+    fn the_levels_are_valid() {
+        assert_eq!(problems(World::classic()), []);
+        assert_eq!(problems(everything()), []);
+    }
+
+    #[test]
+    fn negative_sizes_are_refused() {
+        let mut world = World::classic();
+        world.objects[2].width = -50.0;
+        world.moving_objects[0].height = -1.0;
+        assert_eq!(problems(world), [
+            Problem::NegativeSize { path: path("objects[2]") },
+            Problem::NegativeSize { path: path("moving_objects[0]") },
+        ]);
+    }
+
+    #[test]
+    fn nothing_can_block_the_spawn_point() {
+        let point = |x, y| Point { x, y };
+        let mut world = World::classic();
+        world.objects.push(Rect { x: -10.0, y: -10.0, width: 20.0, height: 20.0 });
+        world.poly_objects.push(Polygon { points: vec![point(-100.0, -100.0), point(100.0, -100.0), point(0.0, 100.0)] });
+        world.moving_objects.push(MovingObject { from: point(10.0, 10.0), to: point(500.0, 10.0), width: 10.0, height: 10.0, speed: 1.0 });
+        assert_eq!(problems(world), [
+            Problem::BlocksSpawn { path: path("objects[4]") },
+            Problem::BlocksSpawn { path: path("poly_objects[1]") },
+            Problem::BlocksSpawn { path: path("moving_objects[3]") },
+        ]);
+    }
+
+    #[test]
+    fn polygons_need_three_points() {
+        let mut world = World::classic();
+        world.poly_objects[0].points.truncate(2);
+        world.poly_objects.push(Polygon { points: Vec::new() });
+        assert_eq!(problems(world), [
+            Problem::TooFewPoints { path: path("poly_objects[0]"), points: 2 },
+            Problem::TooFewPoints { path: path("poly_objects[1]"), points: 0 },
+        ]);
+    }
+
+    #[test]
+    fn movers_have_to_go_somewhere() {
+        let mut world = World::classic();
+        world.moving_objects[1].to = world.moving_objects[1].from;
+        assert_eq!(problems(world), [Problem::ZeroLengthPath { path: path("moving_objects[1]") }]);
+    }
+
+    #[test]
+    fn speeds_have_to_be_positive() {
+        let mut world = everything();
+        world.moving_objects[0].speed = 0.0;
+        world.speed_increases[0].speed_change = -2.0;
+        world.portals[3].kind = PortalKind::Speed { speed: 0.0 };
+        assert_eq!(problems(world), [
+            Problem::NotPositive { path: path("moving_objects[0].speed") },
+            Problem::NotPositive { path: path("speed_increases[0].speed_change") },
+            Problem::NotPositive { path: path("portals[3].speed") },
+        ]);
+    }
+
+    #[test]
+    fn the_finish_has_to_be_past_the_spawn_point() {
+        let mut world = World::classic();
+        world.finish = Some(FinishLine { x: PLAYER_WIDTH });
+        assert_eq!(problems(world), [Problem::FinishAtSpawn { path: path("finish") }]);
+    }
+
+    #[test]
+    fn bad_fields_are_reported_with_their_path() {
+        let mut value = serde_json::to_value(everything()).unwrap();
+        value["moving_objects"][1]["to"]["y"] = "up".into();
+        value["poly_objects"][0]["points"][3].as_object_mut().unwrap().remove("x");
+        value["portals"][1]["size"] = "huge".into();
+        value["checkpoints"] = 5.into();
+        let error = World::from_value(value).unwrap_err();
+        assert_eq!(error.problems, [
+            Problem::MissingField { path: path("poly_objects[0].points[3].x") },
+            Problem::WrongType { path: path("moving_objects[1].to.y"), expected: path("a number") },
+            Problem::WrongType { path: path("checkpoints"), expected: path("a list") },
+            Problem::WrongType { path: path("portals[1].size"), expected: path("\"mini\", \"normal\" or \"large\"") },
+        ]);
+    }
+
+    #[test]
+    fn things_that_arent_levels_are_reported() {
+        let problems = |data| World::parse(data).unwrap_err().problems;
+        assert_eq!(problems("[1, 2]"), [Problem::WrongType { path: path("level"), expected: path("an object") }]);
+        assert_eq!(problems("{}")[0], Problem::MissingField { path: path("objects") });
+        assert!(matches!(&problems("{")[..], [Problem::WrongType { path, .. }] if path == "level"));
+    }
+
+    #[test]
+    fn simulation_world_round_trips() {
+        let others = [("classic".to_owned(), World::classic()), ("everything".to_owned(), everything())];
+        for (path, world) in level_files().into_iter().chain(others) {
+            assert_eq!(sim::World::from_def(&world).to_def(), world, "{}", path);
+        }
+    }
+}